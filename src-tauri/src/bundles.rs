@@ -1,12 +1,20 @@
-use git2::{AutotagOption, FetchOptions, Repository};
+use git2::Repository;
+use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::Manager;
 use tauri_plugin_shell::ShellExt;
 
 use crate::error::Error;
 use crate::git::{get_tag_name_at_head, try_open_repository};
 use crate::log::LogEmitter;
-use crate::{config, git, log, npm};
+use crate::operations::LockTarget;
+use crate::{config, git, log, nodecg, npm, operations, snapshot, trash};
+
+// How long the user has to confirm uninstalling a bundle before having to start over
+const UNINSTALL_CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(5 * 60);
 
 #[derive(PartialEq, Debug)]
 struct ParsedBundleUrl {
@@ -33,14 +41,69 @@ fn parse_bundle_url(url: String) -> Result<ParsedBundleUrl, Error> {
   }
 }
 
+/*
+ * Bundle names are used as directory names, so only names that cannot point outside of the bundles
+ * directory are accepted.
+ */
+pub fn validate_bundle_name(bundle_name: &str) -> Result<(), Error> {
+  let valid = !bundle_name.is_empty()
+    && !bundle_name.starts_with('.')
+    && bundle_name
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+  if valid {
+    Ok(())
+  } else {
+    Err(Error::InvalidBundleName(bundle_name.to_string()))
+  }
+}
+
+/*
+ * Returns the directory of a bundle, making sure it is a direct child of the bundles directory.
+ */
+pub fn resolve_bundle_dir(install_dir: &str, bundle_name: &str) -> Result<PathBuf, Error> {
+  validate_bundle_name(bundle_name)?;
+  let bundles_dir = Path::new(install_dir).join("bundles");
+  let bundle_dir = bundles_dir.join(bundle_name);
+  let is_direct_child = bundle_dir.parent() == Some(bundles_dir.as_path())
+    && bundle_dir.file_name().and_then(|name| name.to_str()) == Some(bundle_name);
+  if !is_direct_child {
+    return Err(Error::InvalidBundleName(bundle_name.to_string()));
+  }
+  Ok(bundle_dir)
+}
+
 #[tauri::command]
 pub async fn install_bundle(handle: tauri::AppHandle, bundle_url: String) -> Result<(), Error> {
-  let logger = LogEmitter::stepped(&handle, "install-bundle", 5);
+  let logger = LogEmitter::start_operation(
+    &handle,
+    "install-bundle",
+    &format!("Install {}", bundle_url),
+    5,
+  );
+  let result = install_bundle_from_url(&handle, bundle_url, &logger).await;
+  logger.finish_operation(&result);
+  result
+}
+
+async fn install_bundle_from_url(
+  handle: &tauri::AppHandle,
+  bundle_url: String,
+  logger: &LogEmitter,
+) -> Result<(), Error> {
   let parsed_url = parse_bundle_url(bundle_url)?;
   logger.emit_progress_stepped(0, &format!("Installing {}...", parsed_url.bundle_name));
 
-  let install_dir = config::with_config(handle.clone(), |c| Ok(c.nodecg_install_dir))?
+  let install_dir = config::with_config(handle.clone(), |c| Ok(c.install_dir()))?
     .ok_or(Error::MissingInstallDir)?;
+  let bundle_path = resolve_bundle_dir(&install_dir, &parsed_url.bundle_name)?
+    .to_string_lossy()
+    .to_string();
+  let _lock = operations::lock(
+    handle,
+    LockTarget::Bundle(install_dir.clone(), parsed_url.bundle_name.clone()),
+    &format!("Install {}", parsed_url.bundle_name),
+  )?;
   let dir_bundles = format!("{}/bundles", install_dir);
   if !Path::new(&dir_bundles).exists() {
     logger.emit_log("Creating missing bundles directory");
@@ -49,22 +112,45 @@ pub async fn install_bundle(handle: tauri::AppHandle, bundle_url: String) -> Res
 
   logger.emit_progress_stepped(1, "Loading version list...");
   let versions = git::fetch_versions_for_url(&parsed_url.bundle_url)?;
+  logger.check_cancelled()?;
+
+  let existed_before = Path::new(&bundle_path).exists();
+  if let Err(e) = clone_bundle(handle, &parsed_url, &bundle_path, &versions, logger).await {
+    // Never remove a directory that was there before the install started
+    let cancelled = matches!(e, Error::Cancelled);
+    if !existed_before
+      && (cancelled || config::with_config(handle.clone(), |c| Ok(c.rollback_failed_installs))?)
+    {
+      logger.emit_log(&format!("Removing {}...", parsed_url.bundle_name));
+      rm_rf::ensure_removed(&bundle_path)?;
+    }
+    return Err(e);
+  }
+  logger.emit_progress_stepped(5, "Done!");
+  Ok(())
+}
 
+async fn clone_bundle(
+  handle: &tauri::AppHandle,
+  parsed_url: &ParsedBundleUrl,
+  bundle_path: &str,
+  versions: &[String],
+  logger: &LogEmitter,
+) -> Result<(), Error> {
   logger.emit_progress_stepped(2, "Cloning repository...");
-  let bundle_path = format!("{}/bundles/{}", install_dir, parsed_url.bundle_name);
-  let repo = Repository::clone(&parsed_url.bundle_url, bundle_path.clone())?;
-  if versions.len() > 1 {
-    let latest_version = versions.first().unwrap();
-    logger.emit_progress_stepped(3, &format!("Checking out version {}...", latest_version));
+  {
+    let repo = git::clone_repository(&parsed_url.bundle_url, bundle_path, logger.cancellation())?;
+    if versions.len() > 1 {
+      let latest_version = versions.first().unwrap();
+      logger.emit_progress_stepped(3, &format!("Checking out version {}...", latest_version));
 
-    git::checkout_version(&repo, latest_version.to_string())?;
+      git::checkout_version(&repo, latest_version.to_string())?;
+    }
   }
+  logger.check_cancelled()?;
 
   logger.emit_progress_stepped(4, "Installing npm dependencies...");
-  let shell = handle.shell();
-  let child = npm::install_npm_dependencies(shell, &bundle_path)?;
-  log::emit_tauri_process_output(&logger, child).await?;
-  logger.emit_progress_stepped(5, "Done!");
+  npm::install_dependencies_and_wait(handle.shell(), bundle_path, logger).await?;
   Ok(())
 }
 
@@ -73,16 +159,15 @@ pub fn fetch_bundle_versions(
   handle: tauri::AppHandle,
   bundle_name: String,
 ) -> Result<Vec<String>, Error> {
-  let install_dir = config::with_config(handle.clone(), |c| Ok(c.nodecg_install_dir))?
+  let install_dir = config::with_config(handle.clone(), |c| Ok(c.install_dir()))?
     .ok_or(Error::MissingInstallDir)?;
-  let bundle_dir = format!("{}/bundles/{}", install_dir, bundle_name);
-  let path = Path::new(&bundle_dir);
+  let path = resolve_bundle_dir(&install_dir, &bundle_name)?;
 
   if !path.exists() {
     return Err(Error::MissingBundle(bundle_name));
   }
 
-  let repo = try_open_repository(path)?;
+  let repo = try_open_repository(&path)?;
 
   if repo.is_none() {
     return Ok(Vec::new());
@@ -94,50 +179,230 @@ pub fn fetch_bundle_versions(
   }
 }
 
+/*
+ * Checks out the given version of an installed bundle and installs its dependencies. A snapshot of
+ * the previous version is taken beforehand, which is restored if the change fails or is cancelled.
+ */
+pub async fn change_bundle_version(
+  handle: &tauri::AppHandle,
+  install_dir: &str,
+  bundle_name: &str,
+  version: &str,
+  logger: &LogEmitter,
+) -> Result<(), Error> {
+  let path = resolve_bundle_dir(install_dir, bundle_name)?;
+
+  if !path.exists() {
+    return Err(Error::MissingBundle(bundle_name.to_string()));
+  }
+  let bundle_dir = path.to_string_lossy().to_string();
+
+  logger.check_cancelled()?;
+  let (snapshot_node_modules, rollback_failed_installs) =
+    config::with_config(handle.clone(), |c| {
+      Ok((c.snapshot_node_modules, c.rollback_failed_installs))
+    })?;
+  logger.emit_log("Creating snapshot of current version...");
+  snapshot::create_snapshot(install_dir, bundle_name, snapshot_node_modules)?;
+
+  if let Err(e) = checkout_bundle_version(handle, &bundle_dir, version, logger).await {
+    if matches!(e, Error::Cancelled) || rollback_failed_installs {
+      logger.emit_log(&format!("Restoring previous version of {}...", bundle_name));
+      let cleanup_logger = logger.uncancellable();
+      if let Err(rollback_err) =
+        snapshot::restore_snapshot(handle, install_dir, bundle_name, &cleanup_logger).await
+      {
+        logger.emit_log(&log::err_to_string(
+          "Failed to restore previous version",
+          rollback_err,
+        ));
+      }
+    }
+
+    // If there was no rollback, or it failed, the dependencies moved into the snapshot still have
+    // to be put back, as the bundle would not be able to start without them.
+    match snapshot::restore_node_modules(install_dir, bundle_name) {
+      Ok(true) => logger.emit_log("Restored previous npm dependencies."),
+      Ok(false) => {}
+      Err(restore_err) => logger.emit_log(&log::err_to_string(
+        "Failed to restore previous npm dependencies",
+        restore_err,
+      )),
+    }
+    return Err(e);
+  }
+  Ok(())
+}
+
+async fn checkout_bundle_version(
+  handle: &tauri::AppHandle,
+  bundle_dir: &str,
+  version: &str,
+  logger: &LogEmitter,
+) -> Result<(), Error> {
+  logger.emit_log(&format!("Checking out version {}...", version));
+  {
+    let repo = Repository::open(bundle_dir)?;
+    let mut remote = git::get_remote(&repo)?;
+    git::fetch_all(&mut remote, logger.cancellation())?;
+    git::checkout_version(&repo, version.to_string())?;
+  }
+  logger.check_cancelled()?;
+
+  logger.emit_log("Installing npm dependencies...");
+  npm::install_dependencies_and_wait(handle.shell(), bundle_dir, logger).await?;
+  Ok(())
+}
+
 #[tauri::command(async)]
 pub async fn set_bundle_version(
   handle: tauri::AppHandle,
   bundle_name: String,
   version: String,
 ) -> Result<(), Error> {
-  let install_dir = config::with_config(handle.clone(), |c| Ok(c.nodecg_install_dir))?
-    .ok_or(Error::MissingInstallDir)?;
-  let logger = LogEmitter::stepped(&handle, "change-bundle-version", 2);
+  let logger = LogEmitter::start_operation(
+    &handle,
+    "change-bundle-version",
+    &format!("Install {} {}", bundle_name, version),
+    1,
+  );
+  let result = install_bundle_version(&handle, &bundle_name, &version, &logger).await;
+  logger.finish_operation(&result);
+  result
+}
+
+async fn install_bundle_version(
+  handle: &tauri::AppHandle,
+  bundle_name: &str,
+  version: &str,
+  logger: &LogEmitter,
+) -> Result<(), Error> {
   logger.emit_progress_stepped(0, &format!("Installing {} {}...", bundle_name, version));
-  let bundle_dir = format!("{}/bundles/{}", install_dir, bundle_name);
-  let path = Path::new(&bundle_dir);
+  let install_dir = config::with_config(handle.clone(), |c| Ok(c.install_dir()))?
+    .ok_or(Error::MissingInstallDir)?;
+  validate_bundle_name(bundle_name)?;
+  let _nodecg_pause = nodecg::pause_for_bundle_change(
+    handle,
+    LockTarget::Bundle(install_dir.clone(), bundle_name.to_string()),
+    &format!("Install {} {}", bundle_name, version),
+  )
+  .await?;
+  change_bundle_version(handle, &install_dir, bundle_name, version, logger).await?;
+  logger.emit_progress_stepped(1, "Done!");
+  Ok(())
+}
 
-  if !path.exists() {
-    return Err(Error::MissingBundle(bundle_name));
+struct PendingUninstall {
+  install_dir: String,
+  bundle_name: String,
+  requested_at: Instant,
+}
+
+/*
+ * Uninstalling a bundle takes a token handed out when the user is asked for confirmation, so a
+ * stray call cannot remove a bundle the user never agreed to remove.
+ */
+#[derive(Default)]
+pub struct UninstallConfirmations(Mutex<HashMap<String, PendingUninstall>>);
+
+impl UninstallConfirmations {
+  fn request(&self, install_dir: &str, bundle_name: &str) -> Result<String, Error> {
+    let mut pending = self
+      .0
+      .lock()
+      .map_err(|e| Error::BundleUninstall(bundle_name.to_string(), e.to_string()))?;
+    pending
+      .retain(|_, uninstall| uninstall.requested_at.elapsed() < UNINSTALL_CONFIRMATION_TIMEOUT);
+
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes)
+      .map_err(|e| Error::BundleUninstall(bundle_name.to_string(), e.to_string()))?;
+    let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    pending.insert(
+      token.clone(),
+      PendingUninstall {
+        install_dir: install_dir.to_string(),
+        bundle_name: bundle_name.to_string(),
+        requested_at: Instant::now(),
+      },
+    );
+    Ok(token)
   }
 
-  {
-    let repo = Repository::open(path)?;
-    let mut remote = git::get_remote(&repo)?;
-    remote.fetch(
-      &[""],
-      Some(FetchOptions::new().download_tags(AutotagOption::All)),
-      None,
-    )?;
-    git::checkout_version(&repo, version.clone())?;
-  }
-
-  let shell = handle.shell();
-  let child = npm::install_npm_dependencies(shell, &bundle_dir)?;
-  logger.emit_progress_stepped(1, "Installing npm dependencies...");
-  log::emit_tauri_process_output(&logger, child).await?;
-  logger.emit_progress_stepped(2, "Done!");
-  Ok(())
+  // Tokens can only be used once, even if they do not match the bundle being uninstalled.
+  fn confirm(&self, install_dir: &str, bundle_name: &str, token: &str) -> Result<(), Error> {
+    let mut pending = self
+      .0
+      .lock()
+      .map_err(|e| Error::BundleUninstall(bundle_name.to_string(), e.to_string()))?;
+    let confirmed = match pending.remove(token) {
+      Some(uninstall) => {
+        uninstall.install_dir == install_dir
+          && uninstall.bundle_name == bundle_name
+          && uninstall.requested_at.elapsed() < UNINSTALL_CONFIRMATION_TIMEOUT
+      }
+      None => false,
+    };
+    if confirmed {
+      Ok(())
+    } else {
+      Err(Error::UnconfirmedUninstall(bundle_name.to_string()))
+    }
+  }
 }
 
+/*
+ * Returns the token uninstall_bundle expects, once the user has been asked to confirm uninstalling
+ * the bundle.
+ */
 #[tauri::command(async)]
-pub fn uninstall_bundle(handle: tauri::AppHandle, bundle_name: String) -> Result<(), Error> {
-  let install_dir = config::with_config(handle.clone(), |c| Ok(c.nodecg_install_dir))?
+pub fn request_bundle_uninstall(
+  handle: tauri::AppHandle,
+  bundle_name: String,
+) -> Result<String, Error> {
+  let install_dir = config::with_config(handle.clone(), |c| Ok(c.install_dir()))?
     .ok_or(Error::MissingInstallDir)?;
+  if !resolve_bundle_dir(&install_dir, &bundle_name)?.exists() {
+    return Err(Error::MissingBundle(bundle_name));
+  }
 
-  rm_rf::remove(format!("{}/bundles/{}", install_dir, bundle_name))
-    .map_err(|e| Error::BundleUninstall(bundle_name, e.to_string()))?;
-  Ok(())
+  handle
+    .state::<UninstallConfirmations>()
+    .request(&install_dir, &bundle_name)
+}
+
+/*
+ * Moves a bundle and its configuration file to the trash, from where it can be restored until the
+ * configured retention period runs out.
+ */
+#[tauri::command(async)]
+pub async fn uninstall_bundle(
+  handle: tauri::AppHandle,
+  bundle_name: String,
+  confirmation_token: String,
+) -> Result<trash::TrashedBundle, Error> {
+  let (install_dir, retention_days) = config::with_config(handle.clone(), |c| {
+    Ok((c.install_dir(), c.trash_retention_days))
+  })?;
+  let install_dir = install_dir.ok_or(Error::MissingInstallDir)?;
+  validate_bundle_name(&bundle_name)?;
+  handle.state::<UninstallConfirmations>().confirm(
+    &install_dir,
+    &bundle_name,
+    &confirmation_token,
+  )?;
+  let _nodecg_pause = nodecg::pause_for_bundle_change(
+    &handle,
+    LockTarget::Bundle(install_dir.clone(), bundle_name.clone()),
+    &format!("Uninstall {}", bundle_name),
+  )
+  .await?;
+
+  if let Err(e) = trash::remove_expired(&install_dir) {
+    eprintln!("Failed to empty expired bundles from the trash: {}", e);
+  }
+  trash::move_to_trash(&install_dir, &bundle_name, retention_days)
+    .map_err(|e| Error::BundleUninstall(bundle_name, e.to_string()))
 }
 
 #[tauri::command(async)]
@@ -145,16 +410,15 @@ pub fn get_bundle_git_tag(
   handle: tauri::AppHandle,
   bundle_name: String,
 ) -> Result<Option<String>, Error> {
-  let install_dir = config::with_config(handle.clone(), |c| Ok(c.nodecg_install_dir))?
+  let install_dir = config::with_config(handle.clone(), |c| Ok(c.install_dir()))?
     .ok_or(Error::MissingInstallDir)?;
-  let bundle_dir = format!("{}/bundles/{}", install_dir, bundle_name);
-  let path = Path::new(&bundle_dir);
+  let path = resolve_bundle_dir(&install_dir, &bundle_name)?;
 
   if !path.exists() {
     return Err(Error::MissingBundle(bundle_name));
   }
 
-  let repo = try_open_repository(path)?;
+  let repo = try_open_repository(&path)?;
   match repo {
     Some(repo) => get_tag_name_at_head(&repo).map_err(|e| Error::Git(e)),
     None => Ok(None),
@@ -187,6 +451,54 @@ mod tests {
     );
   }
 
+  #[test]
+  fn validate_bundle_name_rejects_paths() {
+    assert!(validate_bundle_name("ipl-overlay-controls").is_ok());
+    assert!(validate_bundle_name("bundle_v2.0").is_ok());
+    for name in [
+      "",
+      ".",
+      "..",
+      ".hidden",
+      "../cfg",
+      "/etc",
+      "C:\\bundles",
+      "a/b",
+    ] {
+      assert!(validate_bundle_name(name).is_err(), "{} was accepted", name);
+    }
+  }
+
+  #[test]
+  fn resolve_bundle_dir_rejects_parent_dir() {
+    assert!(resolve_bundle_dir("/nodecg", "test-bundle").is_ok());
+    assert!(resolve_bundle_dir("/nodecg", "..").is_err());
+  }
+
+  #[test]
+  fn uninstall_confirmation_is_single_use() {
+    let confirmations = UninstallConfirmations::default();
+    let token = confirmations.request("/nodecg", "test-bundle").unwrap();
+    assert!(confirmations
+      .confirm("/nodecg", "test-bundle", &token)
+      .is_ok());
+    assert!(confirmations
+      .confirm("/nodecg", "test-bundle", &token)
+      .is_err());
+  }
+
+  #[test]
+  fn uninstall_confirmation_is_bound_to_bundle() {
+    let confirmations = UninstallConfirmations::default();
+    let token = confirmations.request("/nodecg", "test-bundle").unwrap();
+    assert!(confirmations
+      .confirm("/nodecg", "other-bundle", &token)
+      .is_err());
+    assert!(confirmations
+      .confirm("/nodecg", "test-bundle", "0000000000000000")
+      .is_err());
+  }
+
   #[test]
   fn parse_bundle_url_ssh() {
     assert_eq!(
@@ -1,24 +1,261 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde_json::{Map, Value};
 use tauri::{AppHandle, Manager, Runtime};
 use tauri_plugin_store::{with_store, Store, StoreCollection};
 
 use crate::error::Error;
+use crate::log_files::LogFileSink;
+use crate::util::unix_millis;
 
 static STORE_CONFIG_KEY: &str = "config";
+// Increase this and add a migration to CONFIG_MIGRATIONS when the config changes in a way that
+// serde defaults cannot handle.
+pub const CONFIG_VERSION: u32 = 2;
+
+type ConfigMigration = fn(&mut Map<String, Value>);
+
+/*
+ * The migration at index i upgrades a config from version i + 1 to version i + 2. Configs saved
+ * before the version field was added are version 1.
+ */
+const CONFIG_MIGRATIONS: [ConfigMigration; 1] = [migrate_v1_to_v2];
+pub static DEFAULT_PROFILE_NAME: &str = "Default";
+const MAX_PROFILE_NAME_LENGTH: usize = 64;
+
+#[derive(Clone, Default, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LaunchSettings {
+  // Path to the node executable. If missing, node is looked up on the PATH.
+  pub node_path: Option<String>,
+  // Flags passed to node itself, before the NodeCG entry point
+  pub node_args: Vec<String>,
+  // Arguments passed to NodeCG, after the entry point
+  pub nodecg_args: Vec<String>,
+  pub env: HashMap<String, String>,
+}
+
+// What to do when a command needs to modify bundles while NodeCG is running
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum BundleChangePolicy {
+  Refuse,
+  StopAndRestart,
+}
+
+/*
+ * A NodeCG installation, along with the settings used to install and launch it.
+ */
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NodecgProfile {
+  pub name: String,
+  pub install_dir: Option<String>,
+  // The NodeCG version to install. If missing, the latest version is installed.
+  pub nodecg_version: Option<String>,
+  pub launch_settings: LaunchSettings,
+}
+
+impl NodecgProfile {
+  pub fn new(name: &str) -> Self {
+    NodecgProfile {
+      name: name.to_string(),
+      install_dir: None,
+      nodecg_version: None,
+      launch_settings: LaunchSettings::default(),
+    }
+  }
+}
+
+impl Default for NodecgProfile {
+  fn default() -> Self {
+    NodecgProfile::new(DEFAULT_PROFILE_NAME)
+  }
+}
 
 #[derive(serde::Deserialize, serde::Serialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", default)]
 pub struct UserConfig {
-  pub nodecg_install_dir: Option<String>,
+  pub version: u32,
+  pub profiles: Vec<NodecgProfile>,
+  // Name of the profile that commands operate on
+  pub active_profile: String,
   pub enable_error_log: bool,
+  pub rollback_failed_installs: bool,
+  pub snapshot_node_modules: bool,
+  pub shutdown_grace_period_secs: u64,
+  pub auto_restart: bool,
+  pub max_restart_attempts: u32,
+  // Log files older than this are deleted on launch. 0 keeps log files forever.
+  pub log_retention_days: u32,
+  pub bundle_change_policy: BundleChangePolicy,
+  // Uninstalled bundles can be restored from the trash for this many days. 0 keeps them forever.
+  pub trash_retention_days: u32,
 }
 
 impl Default for UserConfig {
   fn default() -> Self {
     UserConfig {
-      nodecg_install_dir: None,
+      version: CONFIG_VERSION,
+      profiles: vec![NodecgProfile::default()],
+      active_profile: DEFAULT_PROFILE_NAME.to_string(),
       enable_error_log: false,
+      rollback_failed_installs: true,
+      snapshot_node_modules: false,
+      shutdown_grace_period_secs: 10,
+      auto_restart: true,
+      max_restart_attempts: 5,
+      log_retention_days: 14,
+      bundle_change_policy: BundleChangePolicy::Refuse,
+      trash_retention_days: 7,
+    }
+  }
+}
+
+impl UserConfig {
+  pub fn profile(&self, name: &str) -> Option<&NodecgProfile> {
+    self.profiles.iter().find(|profile| profile.name == name)
+  }
+
+  pub fn profile_mut(&mut self, name: &str) -> Option<&mut NodecgProfile> {
+    self
+      .profiles
+      .iter_mut()
+      .find(|profile| profile.name == name)
+  }
+
+  pub fn active_profile(&self) -> Option<&NodecgProfile> {
+    self.profile(&self.active_profile)
+  }
+
+  // The install directory of the active profile
+  pub fn install_dir(&self) -> Option<String> {
+    self
+      .active_profile()
+      .and_then(|profile| profile.install_dir.clone())
+  }
+}
+
+pub fn validate_profile_name(name: &str) -> Result<(), Error> {
+  let valid = !name.trim().is_empty()
+    && name.trim() == name
+    && !name.starts_with('.')
+    && name.len() <= MAX_PROFILE_NAME_LENGTH
+    && name
+      .chars()
+      .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.'));
+  if valid {
+    Ok(())
+  } else {
+    Err(Error::InvalidProfileName(name.to_string()))
+  }
+}
+
+/*
+ * The name of the directory a profile is installed into when the user does not pick one.
+ */
+pub fn default_install_dir_name(profile_name: &str) -> String {
+  if profile_name == DEFAULT_PROFILE_NAME {
+    "nodecg".to_string()
+  } else {
+    format!("nodecg-{}", profile_name.replace(' ', "-"))
+  }
+}
+
+// Windows and macOS file systems ignore case by default.
+fn fold_path_case(path: String) -> String {
+  if cfg!(any(target_os = "windows", target_os = "macos")) {
+    path.to_lowercase()
+  } else {
+    path
+  }
+}
+
+/*
+ * Brings an install directory into a form that can be compared to other install directories, so
+ * relative paths, links, trailing separators and differences in case do not hide that two
+ * directories are the same.
+ */
+pub fn normalize_install_dir(install_dir: &str) -> String {
+  let path = Path::new(install_dir);
+  let path = match fs::canonicalize(path) {
+    Ok(path) => path,
+    Err(_) => std::env::current_dir()
+      .map(|dir| dir.join(path))
+      .unwrap_or_else(|_| path.to_path_buf()),
+  };
+  fold_path_case(
+    path
+      .components()
+      .collect::<PathBuf>()
+      .to_string_lossy()
+      .to_string(),
+  )
+}
+
+/*
+ * Makes sure no profile other than the given one uses an install directory, so installing into it
+ * cannot overwrite another profile's installation.
+ */
+pub fn ensure_install_dir_unused(
+  config: &UserConfig,
+  profile_name: &str,
+  install_dir: &str,
+) -> Result<(), Error> {
+  let normalized_install_dir = normalize_install_dir(install_dir);
+  match config.profiles.iter().find(|profile| {
+    profile.name != profile_name
+      && profile.install_dir.as_deref().map_or(false, |dir| {
+        normalize_install_dir(dir) == normalized_install_dir
+      })
+  }) {
+    Some(profile) => Err(Error::InstallDirInUse(
+      install_dir.to_string(),
+      profile.name.clone(),
+    )),
+    None => Ok(()),
+  }
+}
+
+/*
+ * Makes sure a new profile name does not map to the same default install directory as another
+ * profile, e.g. "Main 1" and "Main-1". When renaming a profile, renamed_from is its current name.
+ */
+pub fn ensure_default_install_dir_unused(
+  config: &UserConfig,
+  profile_name: &str,
+  renamed_from: Option<&str>,
+) -> Result<(), Error> {
+  let dir_name = fold_path_case(default_install_dir_name(profile_name));
+  match config.profiles.iter().find(|profile| {
+    profile.name != profile_name
+      && Some(profile.name.as_str()) != renamed_from
+      && fold_path_case(default_install_dir_name(&profile.name)) == dir_name
+  }) {
+    Some(profile) => Err(Error::DefaultInstallDirInUse(
+      profile_name.to_string(),
+      profile.name.clone(),
+    )),
+    None => Ok(()),
+  }
+}
+
+fn validate_profiles(config: &UserConfig) -> Result<(), Error> {
+  for (i, profile) in config.profiles.iter().enumerate() {
+    validate_profile_name(&profile.name)?;
+    if config.profiles[..i].iter().any(|p| p.name == profile.name) {
+      return Err(Error::ProfileExists(profile.name.clone()));
+    }
+    if let Some(install_dir) = &profile.install_dir {
+      ensure_install_dir_unused(config, &profile.name, install_dir)?;
     }
   }
+  if config.active_profile().is_none() {
+    return Err(Error::MissingProfile(config.active_profile.clone()));
+  }
+  Ok(())
 }
 
 fn with_config_store<R: Runtime, T, F: FnOnce(&mut Store<R>) -> tauri_plugin_store::Result<T>>(
@@ -30,21 +267,162 @@ fn with_config_store<R: Runtime, T, F: FnOnce(&mut Store<R>) -> tauri_plugin_sto
   with_store(app.clone(), stores, path, f).map_err(|e| Error::TauriStore(e))
 }
 
-pub fn check_config(app: AppHandle) -> Result<(), Error> {
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigReset {
+  pub reason: String,
+  pub backup_path: String,
+}
+
+// Set when check_config had to reset an unreadable config, until the UI has been told about it
+#[derive(Default)]
+pub struct ConfigResetNotice(Mutex<Option<ConfigReset>>);
+
+fn migrate_v1_to_v2(config: &mut Map<String, Value>) {
+  // Version 1 had a single installation, which becomes the default profile
+  let mut profile = Map::new();
+  profile.insert("name".to_string(), DEFAULT_PROFILE_NAME.into());
+  if let Some(install_dir) = config.remove("nodecgInstallDir") {
+    // Version 1 could save an empty install directory instead of none
+    let install_dir_empty = install_dir
+      .as_str()
+      .map_or(false, |dir| dir.trim().is_empty());
+    let install_dir = if install_dir_empty {
+      Value::Null
+    } else {
+      install_dir
+    };
+    profile.insert("installDir".to_string(), install_dir);
+  }
+  if let Some(launch_settings) = config.remove("launchSettings") {
+    profile.insert("launchSettings".to_string(), launch_settings);
+  }
+  config.insert(
+    "profiles".to_string(),
+    Value::Array(vec![Value::Object(profile)]),
+  );
+  config.insert("activeProfile".to_string(), DEFAULT_PROFILE_NAME.into());
+}
+
+fn migrate_config(mut config: Value) -> Result<Value, String> {
+  let object = config
+    .as_object_mut()
+    .ok_or_else(|| "The config is not a JSON object.".to_string())?;
+  let version = match object.get("version") {
+    Some(version) => version
+      .as_u64()
+      .ok_or_else(|| format!("Invalid config version {}.", version))?,
+    None => 1,
+  };
+  if version == 0 || version > CONFIG_VERSION as u64 {
+    return Err(format!("Config version {} is not supported.", version));
+  }
+
+  for migration in &CONFIG_MIGRATIONS[(version - 1) as usize..] {
+    migration(object);
+  }
+  object.insert("version".to_string(), CONFIG_VERSION.into());
+  Ok(config)
+}
+
+/*
+ * Migrates a stored config to the current version and checks that it can be read.
+ */
+fn read_stored_config(config: Value) -> Result<Value, String> {
+  let migrated = migrate_config(config)?;
+  serde_json::from_value::<UserConfig>(migrated.clone()).map_err(|e| e.to_string())?;
+  Ok(migrated)
+}
+
+/*
+ * The config used in place of an unreadable one. Profiles are kept if they can still be read, so
+ * NodeCG does not have to be set up again.
+ */
+fn fallback_config(config: &Value) -> UserConfig {
+  let profiles = config
+    .get("profiles")
+    .and_then(|profiles| serde_json::from_value::<Vec<NodecgProfile>>(profiles.clone()).ok())
+    .filter(|profiles| !profiles.is_empty());
+  let fallback = match profiles {
+    Some(profiles) => UserConfig {
+      active_profile: profiles[0].name.clone(),
+      profiles,
+      ..UserConfig::default()
+    },
+    // Configs from before profiles were added
+    None => UserConfig {
+      profiles: vec![NodecgProfile {
+        install_dir: config
+          .get("nodecgInstallDir")
+          .and_then(Value::as_str)
+          .filter(|dir| !dir.trim().is_empty())
+          .map(str::to_string),
+        ..NodecgProfile::default()
+      }],
+      ..UserConfig::default()
+    },
+  };
+
+  match config.get("activeProfile").and_then(Value::as_str) {
+    Some(active_profile) if fallback.profile(active_profile).is_some() => UserConfig {
+      active_profile: active_profile.to_string(),
+      ..fallback
+    },
+    _ => fallback,
+  }
+}
+
+fn backup_config(app: &AppHandle, config: &Value) -> Result<String, Error> {
+  let path = app
+    .path()
+    .app_config_dir()?
+    .join(format!("config-backup-{}.json", unix_millis()));
+  fs::write(&path, serde_json::to_string_pretty(config)?)?;
+  Ok(path.to_string_lossy().to_string())
+}
+
+fn save_config_value(app: AppHandle, config: Value) -> Result<(), Error> {
   with_config_store(app, |store| {
-    if let Some(config_value) = store.get(STORE_CONFIG_KEY) {
-      if let Err(e) = serde_json::from_value::<UserConfig>(config_value.clone()) {
-        println!("Error reading application config: {:?}", e);
-        store.insert(
-          STORE_CONFIG_KEY.to_string(),
-          serde_json::to_value(UserConfig::default())?,
+    store.insert(STORE_CONFIG_KEY.to_string(), config)?;
+    store.save()?;
+    Ok(())
+  })
+}
+
+/*
+ * Migrates the stored config to the current version. Configs that cannot be read are backed up next
+ * to the config file before being reset, and the reset is reported to the UI.
+ */
+pub fn check_config(app: AppHandle) -> Result<(), Error> {
+  let mut reset = None;
+  let config_value = with_config_store(app.clone(), |store| {
+    Ok(store.get(STORE_CONFIG_KEY).cloned())
+  })?;
+
+  if let Some(config_value) = config_value {
+    match read_stored_config(config_value.clone()) {
+      Ok(migrated) => {
+        if migrated != config_value {
+          save_config_value(app.clone(), migrated)?;
+        }
+      }
+      Err(reason) => {
+        eprintln!("Error reading application config: {}", reason);
+        let backup_path = backup_config(&app, &config_value)?;
+        save_config_value(
+          app.clone(),
+          serde_json::to_value(fallback_config(&config_value))?,
         )?;
-        store.save()?;
+        reset = Some(ConfigReset {
+          reason,
+          backup_path,
+        });
       }
     }
+  }
 
-    Ok(())
-  })
+  app.manage(ConfigResetNotice(Mutex::new(reset)));
+  Ok(())
 }
 
 pub fn with_config<R: Runtime, T, F: FnOnce(UserConfig) -> tauri_plugin_store::Result<T>>(
@@ -62,17 +440,157 @@ pub fn with_config<R: Runtime, T, F: FnOnce(UserConfig) -> tauri_plugin_store::R
   })
 }
 
+/*
+ * Returns a copy of the active profile.
+ */
+pub fn active_profile<R: Runtime>(app: AppHandle<R>) -> Result<NodecgProfile, Error> {
+  let (name, profile) = with_config(app, |c| {
+    Ok((c.active_profile.clone(), c.active_profile().cloned()))
+  })?;
+  profile.ok_or(Error::MissingProfile(name))
+}
+
+/*
+ * Sets the install directory of the active profile.
+ */
 pub fn update_install_dir(app: AppHandle, install_dir: String) -> Result<(), Error> {
   let mut config = with_config(app.clone(), |c| Ok(c))?;
-  config.nodecg_install_dir = Some(install_dir);
+  let active_profile = config.active_profile.clone();
+  config
+    .profile_mut(&active_profile)
+    .ok_or(Error::MissingProfile(active_profile))?
+    .install_dir = Some(install_dir);
   update_config(app, config)
 }
 
 #[tauri::command]
-pub fn update_config(app: AppHandle, config: UserConfig) -> Result<(), Error> {
+pub fn update_config(app: AppHandle, mut config: UserConfig) -> Result<(), Error> {
+  config.version = CONFIG_VERSION;
+  validate_profiles(&config)?;
+  if let Some(log_files) = app.try_state::<LogFileSink>() {
+    log_files.set_enabled(config.enable_error_log);
+  }
   with_config_store(app, |store| {
     store.insert(STORE_CONFIG_KEY.to_string(), serde_json::to_value(config)?)?;
     store.save()?;
     Ok(())
   })
 }
+
+/*
+ * Returns the config reset that happened on launch, if any. The reset is only reported once.
+ */
+#[tauri::command]
+pub fn take_config_reset(notice: tauri::State<ConfigResetNotice>) -> Option<ConfigReset> {
+  notice.0.lock().ok().and_then(|mut reset| reset.take())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::util::test_util::TestDir;
+  use serde_json::json;
+
+  #[test]
+  fn read_stored_config_migrates_v1() {
+    let migrated = read_stored_config(json!({
+      "nodecgInstallDir": " ",
+      "autoRestart": false
+    }))
+    .unwrap();
+    assert_eq!(
+      json!({
+        "version": CONFIG_VERSION,
+        "profiles": [{ "name": DEFAULT_PROFILE_NAME, "installDir": null }],
+        "activeProfile": DEFAULT_PROFILE_NAME,
+        "autoRestart": false
+      }),
+      migrated
+    );
+  }
+
+  #[test]
+  fn read_stored_config_migrates_v1_to_profiles() {
+    let migrated = read_stored_config(json!({
+      "nodecgInstallDir": "/nodecg",
+      "launchSettings": { "nodeArgs": ["--inspect"] }
+    }))
+    .unwrap();
+    let config = serde_json::from_value::<UserConfig>(migrated).unwrap();
+    let profile = config.active_profile().unwrap();
+    assert_eq!(Some("/nodecg".to_string()), profile.install_dir);
+    assert_eq!(vec!["--inspect"], profile.launch_settings.node_args);
+  }
+
+  #[test]
+  fn read_stored_config_rejects_unreadable_configs() {
+    assert!(read_stored_config(json!({ "autoRestart": "yes" })).is_err());
+    assert!(read_stored_config(json!({ "version": CONFIG_VERSION + 1 })).is_err());
+    assert!(read_stored_config(json!([])).is_err());
+  }
+
+  #[test]
+  fn fallback_config_keeps_install_dir() {
+    let config = json!({ "nodecgInstallDir": "/nodecg", "autoRestart": "yes" });
+    assert_eq!(
+      Some("/nodecg".to_string()),
+      fallback_config(&config).install_dir()
+    );
+  }
+
+  fn config_with_profiles(profiles: &[(&str, Option<&str>)]) -> UserConfig {
+    UserConfig {
+      profiles: profiles
+        .iter()
+        .map(|(name, install_dir)| NodecgProfile {
+          install_dir: install_dir.map(|dir| dir.to_string()),
+          ..NodecgProfile::new(name)
+        })
+        .collect(),
+      ..UserConfig::default()
+    }
+  }
+
+  #[test]
+  fn ensure_install_dir_unused_normalizes_paths() {
+    let dir = TestDir::new("config-install-dir");
+    let install_dir = dir.path_string();
+    let config = config_with_profiles(&[("Main", Some(&install_dir))]);
+
+    dir.write("sub/.keep", "");
+
+    assert!(ensure_install_dir_unused(&config, "Main", &install_dir).is_ok());
+    for other_dir in [
+      format!("{}/", install_dir),
+      format!("{}/sub/..", install_dir),
+    ] {
+      assert!(
+        ensure_install_dir_unused(&config, "Other", &other_dir).is_err(),
+        "{} was accepted",
+        other_dir
+      );
+    }
+    assert!(ensure_install_dir_unused(&config, "Other", &format!("{}-2", install_dir)).is_ok());
+  }
+
+  #[test]
+  fn ensure_default_install_dir_unused_rejects_collisions() {
+    let config = config_with_profiles(&[(DEFAULT_PROFILE_NAME, None), ("Main 1", None)]);
+
+    assert!(ensure_default_install_dir_unused(&config, "Main 2", None).is_ok());
+    assert!(ensure_default_install_dir_unused(&config, "Main-1", None).is_err());
+    assert!(ensure_default_install_dir_unused(&config, "Main-1", Some("Main 1")).is_ok());
+  }
+
+  #[test]
+  fn validate_profile_name_rejects_paths() {
+    assert!(validate_profile_name("Tournament 2024").is_ok());
+    for name in ["", " ", " padded", "..", "a/b", "..\\a"] {
+      assert!(
+        validate_profile_name(name).is_err(),
+        "{} was accepted",
+        name
+      );
+    }
+  }
+}
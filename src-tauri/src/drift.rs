@@ -0,0 +1,456 @@
+use tauri::AppHandle;
+use tauri_plugin_http::reqwest;
+use tauri_plugin_shell::ShellExt;
+
+use crate::error::Error;
+use crate::git::try_open_repository;
+use crate::log::LogEmitter;
+use crate::manifest::{
+  install_manifest_bundle, read_installation_manifest, read_manifest_file,
+  write_manifest_bundle_config, InstallationManifest,
+};
+use crate::operations::LockTarget;
+use crate::{bundles, config, git, log, nodecg, npm, trash};
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+#[serde(tag = "type")]
+pub enum ManifestDifference {
+  NodecgVersion {
+    expected: Option<String>,
+    actual: Option<String>,
+  },
+  MissingBundle {
+    name: String,
+  },
+  ExtraBundle {
+    name: String,
+  },
+  DifferentRemote {
+    name: String,
+    expected: Option<String>,
+    actual: Option<String>,
+  },
+  DifferentRevision {
+    name: String,
+    expected: Option<String>,
+    actual: Option<String>,
+  },
+  DirtyTree {
+    name: String,
+  },
+  ChangedConfig {
+    name: String,
+  },
+}
+
+/*
+ * Lists the ways in which the actual installation differs from the expected one. Fields that are
+ * missing from the expected manifest (e.g. config files that were not exported) are not compared.
+ * Bundle names are expected to be validated already, which read_manifest_file takes care of.
+ */
+pub fn compare_manifests(
+  expected: &InstallationManifest,
+  actual: &InstallationManifest,
+  dirty_bundles: &[String],
+) -> Vec<ManifestDifference> {
+  let mut differences = Vec::new();
+
+  if expected.nodecg_version.is_some() && expected.nodecg_version != actual.nodecg_version {
+    differences.push(ManifestDifference::NodecgVersion {
+      expected: expected.nodecg_version.clone(),
+      actual: actual.nodecg_version.clone(),
+    });
+  }
+
+  for expected_bundle in &expected.bundles {
+    let name = expected_bundle.name.clone();
+    let actual_bundle = match actual.bundles.iter().find(|b| b.name == name) {
+      Some(bundle) => bundle,
+      None => {
+        differences.push(ManifestDifference::MissingBundle { name });
+        continue;
+      }
+    };
+
+    if expected_bundle.remote_url.is_some()
+      && expected_bundle.remote_url != actual_bundle.remote_url
+    {
+      differences.push(ManifestDifference::DifferentRemote {
+        name: name.clone(),
+        expected: expected_bundle.remote_url.clone(),
+        actual: actual_bundle.remote_url.clone(),
+      });
+    }
+
+    // Like BundleManifestEntry::pinned_revision, a tag takes precedence over the commit.
+    if expected_bundle.tag.is_some() {
+      if expected_bundle.tag != actual_bundle.tag {
+        differences.push(ManifestDifference::DifferentRevision {
+          name: name.clone(),
+          expected: expected_bundle.tag.clone(),
+          actual: actual_bundle.tag.clone(),
+        });
+      }
+    } else if expected_bundle.commit.is_some() && expected_bundle.commit != actual_bundle.commit {
+      differences.push(ManifestDifference::DifferentRevision {
+        name: name.clone(),
+        expected: expected_bundle.commit.clone(),
+        actual: actual_bundle.commit.clone(),
+      });
+    }
+
+    if dirty_bundles.contains(&name) {
+      differences.push(ManifestDifference::DirtyTree { name: name.clone() });
+    }
+
+    if expected_bundle.config.is_some() && expected_bundle.config != actual_bundle.config {
+      differences.push(ManifestDifference::ChangedConfig { name });
+    }
+  }
+
+  for actual_bundle in &actual.bundles {
+    if !expected
+      .bundles
+      .iter()
+      .any(|b| b.name == actual_bundle.name)
+    {
+      differences.push(ManifestDifference::ExtraBundle {
+        name: actual_bundle.name.clone(),
+      });
+    }
+  }
+
+  differences
+}
+
+fn find_dirty_bundles(
+  install_dir: &str,
+  manifest: &InstallationManifest,
+) -> Result<Vec<String>, Error> {
+  let mut dirty_bundles = Vec::new();
+  for bundle in &manifest.bundles {
+    let bundle_dir = bundles::resolve_bundle_dir(install_dir, &bundle.name)?;
+    if let Some(repo) = try_open_repository(&bundle_dir)? {
+      if git::is_dirty(&repo)? {
+        dirty_bundles.push(bundle.name.clone());
+      }
+    }
+  }
+
+  Ok(dirty_bundles)
+}
+
+pub fn check_drift(
+  install_dir: &str,
+  expected: &InstallationManifest,
+) -> Result<Vec<ManifestDifference>, Error> {
+  let include_config = expected.bundles.iter().any(|b| b.config.is_some());
+  let actual = read_installation_manifest(install_dir, include_config)?;
+  let dirty_bundles = find_dirty_bundles(install_dir, &actual)?;
+
+  Ok(compare_manifests(expected, &actual, &dirty_bundles))
+}
+
+async fn apply_difference(
+  handle: &AppHandle,
+  install_dir: &str,
+  manifest: &InstallationManifest,
+  difference: &ManifestDifference,
+  dirty_bundles: &[String],
+  remove_extra_bundles: bool,
+  logger: &LogEmitter,
+) -> Result<(), Error> {
+  match difference {
+    ManifestDifference::NodecgVersion { expected, .. } => {
+      let client = reqwest::Client::builder().build()?;
+      let (version, tarball_url) =
+        nodecg::resolve_nodecg_release(&client, expected.as_deref()).await?;
+      logger.emit_log(&format!("Downloading NodeCG {}...", version));
+      let tarball = nodecg::download_nodecg_tarball(&client, &tarball_url, logger).await?;
+      nodecg::extract_nodecg_archive(&tarball, install_dir, logger)?;
+      npm::install_dependencies_and_wait(handle.shell(), install_dir, logger).await?;
+    }
+    ManifestDifference::MissingBundle { name } => {
+      if let Some(bundle) = manifest.bundles.iter().find(|b| &b.name == name) {
+        install_manifest_bundle(handle, install_dir, bundle, logger).await?;
+      }
+    }
+    ManifestDifference::ExtraBundle { name } => {
+      if remove_extra_bundles {
+        logger.emit_log(&format!("Moving {} to the trash...", name));
+        let retention_days = config::with_config(handle.clone(), |c| Ok(c.trash_retention_days))?;
+        trash::move_to_trash(install_dir, name, retention_days)
+          .map_err(|e| Error::BundleUninstall(name.clone(), e.to_string()))?;
+      } else {
+        logger.emit_log(&format!("Leaving {} installed", name));
+      }
+    }
+    ManifestDifference::DifferentRemote { name, expected, .. } => {
+      if let Some(url) = expected {
+        logger.emit_log(&format!("Changing remote of {} to {}...", name, url));
+        let repo = git2::Repository::open(bundles::resolve_bundle_dir(install_dir, name)?)?;
+        let remote_name = git::get_remote(&repo)?
+          .name()
+          .unwrap_or("origin")
+          .to_string();
+        repo.remote_set_url(&remote_name, url)?;
+      }
+    }
+    ManifestDifference::DifferentRevision { name, expected, .. } => {
+      if dirty_bundles.contains(name) {
+        logger.emit_log(&format!(
+          "{} has local changes and will not be changed. Please resolve them manually.",
+          name
+        ));
+      } else if let Some(revision) = expected {
+        bundles::change_bundle_version(handle, install_dir, name, revision, logger).await?;
+      }
+    }
+    ManifestDifference::DirtyTree { .. } => {}
+    ManifestDifference::ChangedConfig { name } => {
+      if let Some(bundle) = manifest.bundles.iter().find(|b| &b.name == name) {
+        logger.emit_log(&format!("Updating configuration of {}...", name));
+        write_manifest_bundle_config(install_dir, bundle)?;
+      }
+    }
+  }
+
+  Ok(())
+}
+
+#[tauri::command(async)]
+pub fn check_installation_drift(
+  handle: AppHandle,
+  path: String,
+) -> Result<Vec<ManifestDifference>, Error> {
+  let install_dir = config::with_config(handle.clone(), |c| Ok(c.install_dir()))?
+    .ok_or(Error::MissingInstallDir)?;
+  let manifest = read_manifest_file(&path)?;
+
+  check_drift(&install_dir, &manifest)
+}
+
+#[tauri::command]
+pub async fn sync_installation_manifest(
+  handle: AppHandle,
+  path: String,
+  remove_extra_bundles: bool,
+) -> Result<(), Error> {
+  let mut logger =
+    LogEmitter::start_operation(&handle, "sync-manifest", &format!("Sync with {}", path), 1);
+  let result = sync_manifest(&handle, &path, remove_extra_bundles, &mut logger).await;
+  logger.finish_operation(&result);
+  result
+}
+
+async fn sync_manifest(
+  handle: &AppHandle,
+  path: &str,
+  remove_extra_bundles: bool,
+  logger: &mut LogEmitter,
+) -> Result<(), Error> {
+  let install_dir = config::with_config(handle.clone(), |c| Ok(c.install_dir()))?
+    .ok_or(Error::MissingInstallDir)?;
+  let _nodecg_pause = nodecg::pause_for_bundle_change(
+    handle,
+    LockTarget::Installation(install_dir.clone()),
+    "Sync installation",
+  )
+  .await?;
+  let manifest = read_manifest_file(path)?;
+  let differences = check_drift(&install_dir, &manifest)?;
+  let dirty_bundles: Vec<String> = differences
+    .iter()
+    .filter_map(|d| match d {
+      ManifestDifference::DirtyTree { name } => Some(name.clone()),
+      _ => None,
+    })
+    .collect();
+
+  logger.set_max_progress_step(differences.len() as u32 + 1);
+  logger.emit_progress_stepped(
+    0,
+    &format!(
+      "Applying {} change(s) to installation...",
+      differences.len()
+    ),
+  );
+
+  for (i, difference) in differences.iter().enumerate() {
+    logger.check_cancelled()?;
+    logger.emit_progress_stepped(i as u32 + 1, &describe_difference(difference));
+    if let Err(e) = apply_difference(
+      handle,
+      &install_dir,
+      &manifest,
+      difference,
+      &dirty_bundles,
+      remove_extra_bundles,
+      logger,
+    )
+    .await
+    {
+      logger.emit_log(&log::err_to_string("Failed to apply change", &e));
+      return Err(e);
+    }
+  }
+
+  logger.emit_progress_stepped(differences.len() as u32 + 1, "Done!");
+  Ok(())
+}
+
+fn describe_difference(difference: &ManifestDifference) -> String {
+  match difference {
+    ManifestDifference::NodecgVersion { expected, actual } => format!(
+      "NodeCG version differs (expected {}, found {})",
+      expected.as_deref().unwrap_or("none"),
+      actual.as_deref().unwrap_or("none")
+    ),
+    ManifestDifference::MissingBundle { name } => format!("{} is not installed", name),
+    ManifestDifference::ExtraBundle { name } => format!("{} is not in the manifest", name),
+    ManifestDifference::DifferentRemote { name, .. } => format!("{} has a different remote", name),
+    ManifestDifference::DifferentRevision {
+      name,
+      expected,
+      actual,
+    } => format!(
+      "{} is at a different version (expected {}, found {})",
+      name,
+      expected.as_deref().unwrap_or("none"),
+      actual.as_deref().unwrap_or("none")
+    ),
+    ManifestDifference::DirtyTree { name } => format!("{} has local changes", name),
+    ManifestDifference::ChangedConfig { name } => format!("{} has a different configuration", name),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::manifest::BundleManifestEntry;
+
+  fn bundle(name: &str, commit: &str) -> BundleManifestEntry {
+    BundleManifestEntry {
+      name: name.to_string(),
+      remote_url: Some(format!("https://github.com/test-user/{}.git", name)),
+      tag: None,
+      commit: Some(commit.to_string()),
+      config: None,
+    }
+  }
+
+  fn manifest(bundles: Vec<BundleManifestEntry>) -> InstallationManifest {
+    InstallationManifest {
+      manifest_version: 1,
+      nodecg_version: Some("2.2.0".to_string()),
+      bundles,
+    }
+  }
+
+  #[test]
+  fn compare_manifests_identical() {
+    let expected = manifest(vec![bundle("bundle-a", "abc")]);
+    let actual = manifest(vec![bundle("bundle-a", "abc")]);
+    assert_eq!(
+      Vec::<ManifestDifference>::new(),
+      compare_manifests(&expected, &actual, &[])
+    );
+  }
+
+  #[test]
+  fn compare_manifests_missing_and_extra_bundles() {
+    let expected = manifest(vec![bundle("bundle-a", "abc")]);
+    let actual = manifest(vec![bundle("bundle-b", "def")]);
+    assert_eq!(
+      vec![
+        ManifestDifference::MissingBundle {
+          name: "bundle-a".to_string()
+        },
+        ManifestDifference::ExtraBundle {
+          name: "bundle-b".to_string()
+        },
+      ],
+      compare_manifests(&expected, &actual, &[])
+    );
+  }
+
+  #[test]
+  fn compare_manifests_different_commit_and_dirty_tree() {
+    let expected = manifest(vec![bundle("bundle-a", "abc")]);
+    let actual = manifest(vec![bundle("bundle-a", "def")]);
+    assert_eq!(
+      vec![
+        ManifestDifference::DifferentRevision {
+          name: "bundle-a".to_string(),
+          expected: Some("abc".to_string()),
+          actual: Some("def".to_string()),
+        },
+        ManifestDifference::DirtyTree {
+          name: "bundle-a".to_string()
+        },
+      ],
+      compare_manifests(&expected, &actual, &["bundle-a".to_string()])
+    );
+  }
+
+  #[test]
+  fn compare_manifests_prefers_tag() {
+    let tagged_bundle = |commit: &str, tag: &str| {
+      let mut bundle = bundle("bundle-a", commit);
+      bundle.tag = Some(tag.to_string());
+      bundle
+    };
+    assert_eq!(
+      Vec::<ManifestDifference>::new(),
+      compare_manifests(
+        &manifest(vec![tagged_bundle("abc", "1.0.0")]),
+        &manifest(vec![tagged_bundle("def", "1.0.0")]),
+        &[]
+      )
+    );
+    assert_eq!(
+      vec![ManifestDifference::DifferentRevision {
+        name: "bundle-a".to_string(),
+        expected: Some("1.0.0".to_string()),
+        actual: Some("1.1.0".to_string()),
+      }],
+      compare_manifests(
+        &manifest(vec![tagged_bundle("abc", "1.0.0")]),
+        &manifest(vec![tagged_bundle("abc", "1.1.0")]),
+        &[]
+      )
+    );
+  }
+
+  #[test]
+  fn compare_manifests_changed_config() {
+    let mut expected_bundle = bundle("bundle-a", "abc");
+    expected_bundle.config = Some(serde_json::json!({ "key": "value" }));
+    let mut actual_bundle = bundle("bundle-a", "abc");
+    actual_bundle.config = Some(serde_json::json!({ "key": "other value" }));
+    assert_eq!(
+      vec![ManifestDifference::ChangedConfig {
+        name: "bundle-a".to_string()
+      }],
+      compare_manifests(
+        &manifest(vec![expected_bundle]),
+        &manifest(vec![actual_bundle]),
+        &[]
+      )
+    );
+  }
+
+  #[test]
+  fn compare_manifests_nodecg_version() {
+    let expected = manifest(vec![]);
+    let mut actual = manifest(vec![]);
+    actual.nodecg_version = Some("2.1.0".to_string());
+    assert_eq!(
+      vec![ManifestDifference::NodecgVersion {
+        expected: Some("2.2.0".to_string()),
+        actual: Some("2.1.0".to_string()),
+      }],
+      compare_manifests(&expected, &actual, &[])
+    );
+  }
+}
@@ -18,6 +18,8 @@ pub enum Error {
   Git(#[from] git2::Error),
   #[error(transparent)]
   RmRf(#[from] rm_rf::Error),
+  #[error(transparent)]
+  Json(#[from] serde_json::Error),
 
   #[error("Error installing NodeCG: {0}")]
   NodeCGInstall(String),
@@ -25,16 +27,69 @@ pub enum Error {
   NodeCGLaunch(String),
   #[error("Error stopping NodeCG: {0}")]
   NodeCGStop(String),
+  #[error("Error reading NodeCG status: {0}")]
+  NodeCGStatus(String),
+  #[error("NodeCG is already running outside of NCGMGR ({1}, PID {0}).")]
+  NodeCGRunningExternally(u32, String),
+  #[error("NodeCG is running. Stop it first, or let NCGMGR restart it in the settings.")]
+  NodeCGRunning,
+  #[error("Port {0} is already in use by another application.")]
+  PortInUse(u16),
+  #[error("Port {0} is already used by NodeCG from profile {1}.")]
+  PortUsedByProfile(u16, String),
   #[error("NodeCG install directory is not configured")]
   MissingInstallDir,
+  #[error("Profile {0} does not exist.")]
+  MissingProfile(String),
+  #[error("A profile named {0} already exists.")]
+  ProfileExists(String),
+  #[error("\"{0}\" is not a valid profile name.")]
+  InvalidProfileName(String),
+  #[error("NodeCG is running from profile {0}. Stop it first.")]
+  ProfileRunning(String),
+  #[error("The last profile cannot be deleted.")]
+  LastProfile,
+  #[error("{0} is already used by profile {1}.")]
+  InstallDirInUse(String, String),
+  #[error("Profile {0} would use the same default install directory as profile {1}.")]
+  DefaultInstallDirInUse(String, String),
   #[error("Could not determine default install directory for NodeCG. Please select one manually.")]
   CannotCreateDefaultInstallDir,
   #[error("Bundle {0} is not installed.")]
   MissingBundle(String),
   #[error("Failed to uninstall bundle {0}: {1}")]
   BundleUninstall(String, String),
+  #[error("Uninstalling bundle {0} was not confirmed. Please try again.")]
+  UnconfirmedUninstall(String),
+  #[error("\"{0}\" is not a valid bundle name.")]
+  InvalidBundleName(String),
+  #[error("Bundle {0} could not be found in the trash.")]
+  MissingTrashedBundle(String),
+  #[error("Failed to restore bundle {0}: {1}")]
+  BundleRestore(String, String),
   #[error("Invalid bundle URL provided.")]
   InvalidBundleURL,
+  #[error("No snapshot exists for bundle {0}.")]
+  MissingSnapshot(String),
+  #[error("Manifest version {0} is not supported by this version of NCGMGR.")]
+  UnsupportedManifest(u32),
+  #[error("Bundle {0} cannot be installed from the manifest as it has no git remote.")]
+  ManifestBundleWithoutRemote(String),
+
+  #[error("Error accessing log file: {0}")]
+  LogFile(String),
+  #[error("Error reading log history: {0}")]
+  LogHistory(String),
+  #[error("Error tracking operation: {0}")]
+  Operation(String),
+  #[error("The operation was cancelled.")]
+  Cancelled,
+  #[error("Another operation ({0}) is in progress for {1}. Please wait for it to finish.")]
+  OperationConflict(String, String),
+  #[error("The NodeCG installation is being modified by another instance of NCGMGR (PID {0}).")]
+  InstallationLocked(u32),
+  #[error("The NodeCG installation is being modified by another instance of NCGMGR.")]
+  InstallationLockedByUnknownInstance,
 
   #[error("Error installing npm dependencies: {0}")]
   NPMInstall(String),
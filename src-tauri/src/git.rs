@@ -1,9 +1,16 @@
-use git2::{Direction, ErrorCode, Remote, Repository};
+use git2::build::RepoBuilder;
+use git2::{
+  AutotagOption, Direction, ErrorCode, FetchOptions, Remote, RemoteCallbacks, Repository,
+  StatusOptions,
+};
 use itertools::Itertools;
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::path::Path;
 
+use crate::error::Error;
+use crate::operations::CancellationToken;
+
 /*
  * Returns:
  * Ok(Some(Repository)) if the repository is found
@@ -51,6 +58,46 @@ pub fn fetch_versions(mut remote: Remote) -> Result<Vec<String>, git2::Error> {
   )
 }
 
+// Fetch options that abort the transfer once the operation has been cancelled
+fn cancellable_fetch_options(cancellation: &CancellationToken) -> FetchOptions<'_> {
+  let mut callbacks = RemoteCallbacks::new();
+  callbacks.transfer_progress(move |_| !cancellation.is_cancelled());
+  let mut fetch_options = FetchOptions::new();
+  fetch_options.remote_callbacks(callbacks);
+  fetch_options
+}
+
+// Replaces the error git2 reports for aborted transfers with Error::Cancelled.
+fn map_cancelled(error: git2::Error, cancellation: &CancellationToken) -> Error {
+  if cancellation.is_cancelled() {
+    Error::Cancelled
+  } else {
+    Error::Git(error)
+  }
+}
+
+pub fn clone_repository<P: AsRef<Path>>(
+  url: &str,
+  path: P,
+  cancellation: &CancellationToken,
+) -> Result<Repository, Error> {
+  RepoBuilder::new()
+    .fetch_options(cancellable_fetch_options(cancellation))
+    .clone(url, path.as_ref())
+    .map_err(|e| map_cancelled(e, cancellation))
+}
+
+/*
+ * Fetches all branches and tags from the remote.
+ */
+pub fn fetch_all(remote: &mut Remote, cancellation: &CancellationToken) -> Result<(), Error> {
+  let mut fetch_options = cancellable_fetch_options(cancellation);
+  fetch_options.download_tags(AutotagOption::All);
+  remote
+    .fetch(&[""], Some(&mut fetch_options), None)
+    .map_err(|e| map_cancelled(e, cancellation))
+}
+
 pub fn checkout_version(repo: &Repository, version: String) -> Result<(), git2::Error> {
   let (object, reference) = repo.revparse_ext(&version)?;
   repo.checkout_tree(&object, None)?;
@@ -62,6 +109,32 @@ pub fn checkout_version(repo: &Repository, version: String) -> Result<(), git2::
   Ok(())
 }
 
+/*
+ * Returns something that can be passed to checkout_version to return to the current HEAD:
+ * The reference name if HEAD points to a branch, or the commit ID if HEAD is detached.
+ */
+pub fn get_head_revision(repo: &Repository) -> Result<String, git2::Error> {
+  let head = repo.head()?;
+  match head.name() {
+    Some(name) if !repo.head_detached()? => Ok(name.to_string()),
+    _ => Ok(head.peel_to_commit()?.id().to_string()),
+  }
+}
+
+/*
+ * Whether the working tree has changes compared to HEAD. Untracked files count as changes, but
+ * ignored files such as node_modules do not.
+ */
+pub fn is_dirty(repo: &Repository) -> Result<bool, git2::Error> {
+  let statuses = repo.statuses(Some(
+    StatusOptions::new()
+      .include_untracked(true)
+      .include_ignored(false),
+  ))?;
+
+  Ok(!statuses.is_empty())
+}
+
 pub fn get_remote(repo: &Repository) -> Result<Remote, git2::Error> {
   let remotes = repo.remotes()?;
   let remote_name = remotes
@@ -69,7 +142,7 @@ pub fn get_remote(repo: &Repository) -> Result<Remote, git2::Error> {
     .filter(|remote| remote.is_some())
     .map(|remote| remote.unwrap())
     .find_or_first(|remote| remote == &"origin")
-    .expect("Could not find any remotes.");
+    .ok_or_else(|| git2::Error::from_str("Could not find any remotes."))?;
 
   let remote = repo.find_remote(&remote_name)?;
 
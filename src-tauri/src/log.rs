@@ -1,14 +1,23 @@
+use std::collections::VecDeque;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 use tauri::async_runtime::{spawn, JoinHandle, Receiver};
 use tauri::Manager;
 use tauri_plugin_shell::process::{CommandEvent, TerminatedPayload};
 
+use crate::error::Error;
+use crate::log_files::LogFileSink;
+use crate::log_history::LogHistory;
+use crate::operations::{CancellationToken, OperationRegistry};
+use crate::util::unix_millis;
+
 #[derive(Clone)]
 pub struct LogEmitter {
   handle: tauri::AppHandle,
   key: String,
   max_progress_step: Option<u32>,
+  operation_id: Option<u64>,
+  cancellation: CancellationToken,
 }
 
 impl LogEmitter {
@@ -17,6 +26,8 @@ impl LogEmitter {
       handle: handle.clone(),
       key: key.to_string(),
       max_progress_step: None,
+      operation_id: None,
+      cancellation: CancellationToken::default(),
     }
   }
 
@@ -26,60 +37,330 @@ impl LogEmitter {
     emitter
   }
 
+  /*
+   * Registers a new operation and returns an emitter whose events carry its ID. finish_operation
+   * has to be called with the result once the operation is done.
+   */
+  pub fn start_operation(
+    handle: &tauri::AppHandle,
+    key: &str,
+    description: &str,
+    max_progress_step: u32,
+  ) -> LogEmitter {
+    let mut emitter = LogEmitter::stepped(handle, key, max_progress_step);
+    if let Some(operations) = handle.try_state::<OperationRegistry>() {
+      match operations.start(key, description) {
+        Ok(operation) => {
+          emitter.operation_id = Some(operation.id);
+          emitter.cancellation = operation.cancellation.clone();
+          emit_event(handle, "operation-update", operation);
+        }
+        Err(e) => eprintln!("Failed to register operation {}: {}", description, e),
+      }
+    }
+    emitter
+  }
+
+  pub fn finish_operation<T>(&self, result: &Result<T, Error>) -> () {
+    let id = match self.operation_id {
+      Some(id) => id,
+      None => return,
+    };
+    if let Some(operations) = self.handle.try_state::<OperationRegistry>() {
+      match operations.finish(id, result.as_ref().err()) {
+        Ok(Some(operation)) => emit_event(&self.handle, "operation-update", operation),
+        Ok(None) => {}
+        Err(e) => eprintln!("Failed to finish operation {}: {}", id, e),
+      }
+    }
+  }
+
+  pub fn cancellation(&self) -> &CancellationToken {
+    &self.cancellation
+  }
+
+  // Returns Error::Cancelled if the user has cancelled the operation this emitter belongs to.
+  pub fn check_cancelled(&self) -> Result<(), Error> {
+    self.cancellation.check()
+  }
+
+  /*
+   * Returns an emitter for the same operation that ignores cancellation, for cleaning up after
+   * an operation has been cancelled.
+   */
+  pub fn uncancellable(&self) -> LogEmitter {
+    let mut emitter = self.clone();
+    emitter.cancellation = CancellationToken::default();
+    emitter
+  }
+
+  pub fn set_max_progress_step(&mut self, max_progress_step: u32) -> () {
+    self.max_progress_step = Some(max_progress_step);
+  }
+
   pub fn emit_log(&self, msg: &str) -> () {
-    self
-      .handle
-      .emit(
-        &format!("log:{}", self.key),
-        LogPayload {
-          message: msg.to_string(),
-        },
-      )
-      .expect("Failed to emit log message");
-  }
-  
+    self.emit_log_payload(LogPayload::new(msg, None));
+  }
+
+  fn emit_log_payload(&self, mut payload: LogPayload) -> () {
+    payload.operation_id = self.operation_id;
+    if let Some(history) = self.handle.try_state::<LogHistory>() {
+      match history.record(&self.key, payload.clone()) {
+        Ok(id) => payload.id = id,
+        Err(e) => eprintln!("Failed to record log history: {}", e),
+      }
+    }
+    if let Some(log_files) = self.handle.try_state::<LogFileSink>() {
+      if let Err(e) = log_files.write(&self.key, &payload.message) {
+        eprintln!("Failed to write to log file: {}", e);
+      }
+    }
+
+    let message = payload.message.clone();
+    if let Err(e) = self.try_emit("log", payload) {
+      self.report_emit_failure("log message", &message, e);
+    }
+  }
+
   pub fn emit_progress(&self, message: &str) -> () {
-    self
-      .handle
-      .emit(
-        &format!("progress:{}", self.key),
-        ProgressPayload {
-          message: message.to_string(),
-          step: None,
-          max_step: self.max_progress_step
-        }
-      )
-      .expect("Failed to emit progress message");
+    self.emit_progress_payload(ProgressPayload {
+      message: message.to_string(),
+      step: None,
+      max_step: self.max_progress_step,
+      operation_id: self.operation_id,
+    });
   }
 
   pub fn emit_progress_stepped(&self, step: u32, message: &str) -> () {
-    self
-      .handle
-      .emit(
-        &format!("progress:{}", self.key),
-        ProgressPayload {
-          message: message.to_string(),
-          step: Some(step),
-          max_step: self.max_progress_step,
-        },
-      )
-      .expect("Failed to emit progress message");
+    self.emit_progress_payload(ProgressPayload {
+      message: message.to_string(),
+      step: Some(step),
+      max_step: self.max_progress_step,
+      operation_id: self.operation_id,
+    });
+  }
+
+  fn emit_progress_payload(&self, payload: ProgressPayload) -> () {
+    let message = payload.message.clone();
+    if let Err(e) = self.try_emit("progress", payload) {
+      self.report_emit_failure("progress message", &message, e);
+    }
   }
 
   pub fn emit_process_closure(&self, payload: &ProcessResult) -> () {
+    let exit_payload = ProcessExitPayload {
+      operation_id: self.operation_id,
+      result: payload,
+    };
+    if let Err(e) = self.try_emit("process-exit", exit_payload) {
+      self.report_emit_failure("process exit", &payload.to_string(), e);
+    }
+  }
+
+  /*
+   * Sends an event to the UI. Emitting fails when the webview is gone, e.g. while the app is
+   * shutting down, which callers should not treat as a reason to stop what they are doing.
+   */
+  fn try_emit<S: serde::Serialize + Clone>(
+    &self,
+    event: &str,
+    payload: S,
+  ) -> Result<(), tauri::Error> {
     self
       .handle
-      .emit(
-        &format!("process-exit:{}", self.key),
-        payload,
-      )
-      .expect("Failed to emit log message for process closure");
+      .emit(&format!("{}:{}", event, self.key), payload)
+  }
+
+  // Writes messages that could not be sent to the UI to stderr, so they are not lost entirely.
+  fn report_emit_failure(&self, kind: &str, message: &str, error: tauri::Error) -> () {
+    eprintln!(
+      "Failed to emit {} for {} ({}): {}",
+      kind,
+      self.key,
+      error,
+      message.trim_end()
+    );
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+  Stdout,
+  Stderr,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+  Trace,
+  Debug,
+  Info,
+  Warn,
+  Error,
+}
+
+impl LogLevel {
+  fn parse(level: &str) -> Option<Self> {
+    match level {
+      "trace" => Some(LogLevel::Trace),
+      "debug" => Some(LogLevel::Debug),
+      "info" => Some(LogLevel::Info),
+      "warn" => Some(LogLevel::Warn),
+      "error" => Some(LogLevel::Error),
+      _ => None,
+    }
   }
 }
 
+/*
+ * The parts of a line logged by NodeCG, which look like "[bundle-name] info: message" or
+ * "info: [bundle-name] message".
+ */
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct ParsedLogLine {
+  pub level: LogLevel,
+  // The bundle or NodeCG component that logged the line
+  pub bundle: Option<String>,
+  pub text: String,
+}
+
 #[derive(Clone, serde::Serialize)]
-struct LogPayload {
-  message: String,
+#[serde(rename_all = "camelCase")]
+pub struct LogPayload {
+  // Assigned by LogHistory, 0 if the line was not recorded
+  pub id: u64,
+  pub operation_id: Option<u64>,
+  // The line as it was logged, including ANSI color codes
+  pub message: String,
+  // Set for lines of process output
+  pub stream: Option<LogStream>,
+  // Milliseconds since the Unix epoch
+  pub timestamp: u64,
+  #[serde(flatten)]
+  pub parsed: Option<ParsedLogLine>,
+}
+
+impl LogPayload {
+  pub(crate) fn new(message: &str, stream: Option<LogStream>) -> Self {
+    LogPayload {
+      id: 0,
+      operation_id: None,
+      message: message.to_string(),
+      stream,
+      timestamp: unix_millis(),
+      parsed: stream.and_then(|_| parse_log_line(&strip_ansi_codes(message))),
+    }
+  }
+}
+
+fn strip_ansi_codes(line: &str) -> String {
+  let mut result = String::with_capacity(line.len());
+  let mut chars = line.chars().peekable();
+  while let Some(c) = chars.next() {
+    if c != '\u{1b}' {
+      result.push(c);
+      continue;
+    }
+    // Control sequences end with a character in the range @ to ~, other escapes are two characters
+    if chars.next_if_eq(&'[').is_some() {
+      while let Some(c) = chars.next() {
+        if ('@'..='~').contains(&c) {
+          break;
+        }
+      }
+    } else {
+      chars.next();
+    }
+  }
+  result
+}
+
+fn split_label(line: &str) -> Option<(&str, &str)> {
+  let rest = line.strip_prefix('[')?;
+  let (label, rest) = rest.split_once(']')?;
+  Some((label, rest.trim_start()))
+}
+
+fn split_level(line: &str) -> Option<(LogLevel, &str)> {
+  let (level, rest) = line.split_once(':')?;
+  Some((LogLevel::parse(level.trim())?, rest.trim_start()))
+}
+
+fn parse_log_line(line: &str) -> Option<ParsedLogLine> {
+  let line = line.trim();
+  let (level, bundle, text) = match split_label(line) {
+    Some((bundle, rest)) => {
+      let (level, text) = split_level(rest)?;
+      (level, Some(bundle), text)
+    }
+    None => {
+      let (level, rest) = split_level(line)?;
+      match split_label(rest) {
+        Some((bundle, text)) => (level, Some(bundle), text),
+        None => (level, None, rest),
+      }
+    }
+  };
+
+  Some(ParsedLogLine {
+    level,
+    bundle: bundle.map(|bundle| bundle.to_string()),
+    text: text.to_string(),
+  })
+}
+
+// Output without a line break is emitted as a line once this many bytes have been buffered.
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/*
+ * Splits process output into lines. Output arrives in chunks that may end in the middle of a line
+ * or even a UTF-8 character, so anything after the last line break is kept until more output
+ * arrives.
+ */
+#[derive(Default)]
+struct LineAssembler {
+  buffer: Vec<u8>,
+}
+
+impl LineAssembler {
+  fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+    // The buffered bytes never contain a line break, so only the new chunk has to be searched.
+    let chunk_start = self.buffer.len();
+    self.buffer.extend_from_slice(chunk);
+    let mut lines = Vec::new();
+    let mut line_start = 0;
+    for (offset, byte) in chunk.iter().enumerate() {
+      if *byte == b'\n' {
+        let line_end = chunk_start + offset + 1;
+        lines.push(decode_line(&self.buffer[line_start..line_end]));
+        line_start = line_end;
+      }
+    }
+    self.buffer.drain(..line_start);
+
+    if self.buffer.len() >= MAX_LINE_LENGTH {
+      lines.extend(self.finish());
+    }
+    lines
+  }
+
+  // Returns the incomplete line left over once the process has exited.
+  fn finish(&mut self) -> Option<String> {
+    if self.buffer.is_empty() {
+      None
+    } else {
+      let line = decode_line(&self.buffer);
+      self.buffer.clear();
+      Some(line)
+    }
+  }
+}
+
+fn decode_line(line: &[u8]) -> String {
+  String::from_utf8_lossy(line)
+    .trim_end_matches(['\n', '\r'])
+    .to_string()
 }
 
 #[derive(Clone, serde::Serialize)]
@@ -88,19 +369,34 @@ struct ProgressPayload {
   message: String,
   step: Option<u32>,
   max_step: Option<u32>,
+  operation_id: Option<u64>,
+}
+
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProcessExitPayload<'a> {
+  operation_id: Option<u64>,
+  #[serde(flatten)]
+  result: &'a ProcessResult,
 }
 
+// How many lines of stderr output are kept around to explain a failed process
+const STDERR_TAIL_LINES: usize = 10;
+
 #[derive(Clone, serde::Serialize)]
 pub struct ProcessResult {
   pub code: Option<i32>,
   pub success: bool,
+  #[serde(skip)]
+  pub stderr_tail: Vec<String>,
 }
 
 impl ProcessResult {
-  fn from_terminated_payload(payload: &TerminatedPayload) -> Self {
+  fn from_terminated_payload(payload: &TerminatedPayload, stderr_tail: Vec<String>) -> Self {
     ProcessResult {
       code: payload.code,
-      success: is_process_termination_successful(payload)
+      success: is_process_termination_successful(payload),
+      stderr_tail,
     }
   }
 }
@@ -108,47 +404,99 @@ impl ProcessResult {
 impl Display for ProcessResult {
   fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
     if let Some(code) = self.code {
-      write!(f, "Process exited with code {}", code) 
+      write!(f, "Process exited with code {}", code)
     } else {
       write!(f, "Process exited")
     }
   }
 }
 
+// A process killed by a signal, or one without an exit code, did not finish its work.
 fn is_process_termination_successful(payload: &TerminatedPayload) -> bool {
-  return payload.signal.is_some()
-    || payload.code.is_none()
-    || (payload.code.is_some() && payload.code.unwrap().eq(&0));
+  payload.code == Some(0)
+}
+
+fn push_stderr_tail(stderr_tail: &mut VecDeque<String>, line: String) {
+  if stderr_tail.len() == STDERR_TAIL_LINES {
+    stderr_tail.pop_front();
+  }
+  stderr_tail.push_back(line);
 }
 
 pub fn emit_tauri_process_output(
+  logger: &LogEmitter,
+  receiver: Receiver<CommandEvent>,
+) -> JoinHandle<Option<ProcessResult>> {
+  emit_tauri_process_output_with(logger, receiver, |_| {})
+}
+
+/*
+ * Like emit_tauri_process_output, but calls on_line with every line of output before it is
+ * emitted.
+ */
+pub fn emit_tauri_process_output_with<F: FnMut(&LogPayload) + Send + 'static>(
   logger: &LogEmitter,
   mut receiver: Receiver<CommandEvent>,
+  mut on_line: F,
 ) -> JoinHandle<Option<ProcessResult>> {
   let process_logger = logger.clone();
   spawn(async move {
+    let mut emit_line = |stream: LogStream, line: &str| {
+      let payload = LogPayload::new(line, Some(stream));
+      on_line(&payload);
+      process_logger.emit_log_payload(payload);
+    };
     let mut result: Option<ProcessResult> = None;
+    let mut stderr_tail: VecDeque<String> = VecDeque::with_capacity(STDERR_TAIL_LINES);
+    let mut stdout_lines = LineAssembler::default();
+    let mut stderr_lines = LineAssembler::default();
+
     while let Some(item) = receiver.recv().await {
-      process_logger.emit_log(&*match item {
-        CommandEvent::Stderr(msg) => String::from_utf8(msg)
-          .unwrap_or_else(|e| format!("Failed to decode output: {}", e.to_string())),
-        CommandEvent::Stdout(msg) => String::from_utf8(msg)
-          .unwrap_or_else(|e| format!("Failed to decode output: {}", e.to_string())),
-        CommandEvent::Error(msg) => msg,
+      match item {
+        CommandEvent::Stdout(chunk) => {
+          for line in stdout_lines.push(&chunk) {
+            emit_line(LogStream::Stdout, &line);
+          }
+        }
+        CommandEvent::Stderr(chunk) => {
+          for line in stderr_lines.push(&chunk) {
+            emit_line(LogStream::Stderr, &line);
+            push_stderr_tail(&mut stderr_tail, line);
+          }
+        }
+        CommandEvent::Error(msg) => process_logger.emit_log(&msg),
         CommandEvent::Terminated(payload) => {
-          let process_result = ProcessResult::from_terminated_payload(&payload);
+          if let Some(line) = stdout_lines.finish() {
+            emit_line(LogStream::Stdout, &line);
+          }
+          if let Some(line) = stderr_lines.finish() {
+            emit_line(LogStream::Stderr, &line);
+            push_stderr_tail(&mut stderr_tail, line);
+          }
+
+          let process_result =
+            ProcessResult::from_terminated_payload(&payload, stderr_tail.drain(..).collect());
           process_logger.emit_process_closure(&process_result);
-          let log_line = process_result.to_string();
+          process_logger.emit_log(&process_result.to_string());
           result = Some(process_result);
-          log_line
         }
-        _ => "".to_string(),
-      })
+        _ => {}
+      }
     }
     result
   })
 }
 
+/*
+ * Emits an app-wide event. Failures are written to stderr instead of being returned, as the UI
+ * missing an update should not interrupt the code sending it.
+ */
+pub fn emit_event<S: serde::Serialize + Clone>(handle: &tauri::AppHandle, event: &str, payload: S) {
+  if let Err(e) = handle.emit(event, payload) {
+    eprintln!("Failed to emit {}: {}", event, e);
+  }
+}
+
 pub fn err_to_string<T: fmt::Display>(msg: &str, err: T) -> String {
   format!("{}: {}", msg, err.to_string())
 }
@@ -156,3 +504,99 @@ pub fn err_to_string<T: fmt::Display>(msg: &str, err: T) -> String {
 pub fn format_error<F, T: fmt::Display>(msg: &str, err: T) -> Result<F, String> {
   Err(err_to_string(msg, err))
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn strip_ansi_codes_removes_colors() {
+    assert_eq!(
+      "info: [nodecg] Listening",
+      strip_ansi_codes("\u{1b}[32minfo\u{1b}[39m: [nodecg] Listening")
+    );
+  }
+
+  #[test]
+  fn parse_log_line_bundle_first() {
+    assert_eq!(
+      Some(ParsedLogLine {
+        level: LogLevel::Error,
+        bundle: Some("test-bundle".to_string()),
+        text: "Something went wrong".to_string()
+      }),
+      parse_log_line("[test-bundle] error: Something went wrong")
+    );
+  }
+
+  #[test]
+  fn parse_log_line_level_first() {
+    assert_eq!(
+      Some(ParsedLogLine {
+        level: LogLevel::Info,
+        bundle: Some("nodecg/lib/server".to_string()),
+        text: "Listening on port 9090".to_string()
+      }),
+      parse_log_line("info: [nodecg/lib/server] Listening on port 9090")
+    );
+  }
+
+  #[test]
+  fn parse_log_line_unstructured() {
+    assert_eq!(None, parse_log_line("Some other output"));
+    assert_eq!(None, parse_log_line("[test-bundle] note: Not a log level"));
+  }
+
+  #[test]
+  fn process_termination_success() {
+    let exited = |code, signal| TerminatedPayload { code, signal };
+    assert!(is_process_termination_successful(&exited(Some(0), None)));
+    assert!(!is_process_termination_successful(&exited(Some(1), None)));
+    assert!(!is_process_termination_successful(&exited(None, Some(9))));
+    assert!(!is_process_termination_successful(&exited(None, None)));
+  }
+
+  #[test]
+  fn line_assembler_joins_split_lines() {
+    let mut assembler = LineAssembler::default();
+    assert_eq!(Vec::<String>::new(), assembler.push(b"Hello, "));
+    assert_eq!(
+      vec!["Hello, World!".to_string(), "Second line".to_string()],
+      assembler.push(b"World!\r\nSecond line\nThird")
+    );
+    assert_eq!(Some("Third".to_string()), assembler.finish());
+    assert_eq!(None, assembler.finish());
+  }
+
+  #[test]
+  fn line_assembler_decodes_split_characters() {
+    let mut assembler = LineAssembler::default();
+    let text = "✔ Done\n".as_bytes();
+    assert_eq!(Vec::<String>::new(), assembler.push(&text[..1]));
+    assert_eq!(vec!["✔ Done".to_string()], assembler.push(&text[1..]));
+  }
+
+  #[test]
+  fn line_assembler_replaces_invalid_utf8() {
+    let mut assembler = LineAssembler::default();
+    assert_eq!(
+      vec!["a\u{FFFD}b".to_string()],
+      assembler.push(&[b'a', 0xFF, b'b', b'\n'])
+    );
+  }
+
+  #[test]
+  fn line_assembler_flushes_long_lines() {
+    let mut assembler = LineAssembler::default();
+    let line = "a".repeat(MAX_LINE_LENGTH);
+    assert_eq!(
+      Vec::<String>::new(),
+      assembler.push(&line.as_bytes()[..MAX_LINE_LENGTH - 1])
+    );
+    assert_eq!(
+      vec![line],
+      assembler.push(&line.as_bytes()[MAX_LINE_LENGTH - 1..])
+    );
+    assert_eq!(vec!["b".to_string()], assembler.push(b"b\n"));
+  }
+}
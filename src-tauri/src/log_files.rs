@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tauri::{AppHandle, Manager};
+
+use crate::config;
+use crate::error::Error;
+use crate::util::unix_millis;
+
+// Once a log file grows past this size, further lines are written to a new file.
+const MAX_LOG_FILE_SIZE: u64 = 10 * 1024 * 1024;
+static LOG_FILE_EXTENSION: &str = "log";
+
+struct OpenLogFile {
+  file: File,
+  size: u64,
+}
+
+/*
+ * Writes log messages to files in the app log directory while the error log is enabled. Each log
+ * key gets its own file per session, named <key>-<unix timestamp in milliseconds>.log.
+ */
+pub struct LogFileSink {
+  dir: PathBuf,
+  enabled: AtomicBool,
+  files: Mutex<HashMap<String, OpenLogFile>>,
+}
+
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogFileInfo {
+  pub name: String,
+  pub key: String,
+  // Milliseconds since the Unix epoch
+  pub created_at: u64,
+  pub size: u64,
+}
+
+impl LogFileSink {
+  pub fn new(dir: PathBuf, enabled: bool) -> Self {
+    LogFileSink {
+      dir,
+      enabled: AtomicBool::new(enabled),
+      files: Mutex::new(HashMap::new()),
+    }
+  }
+
+  pub fn set_enabled(&self, enabled: bool) {
+    self.enabled.store(enabled, Ordering::Relaxed);
+    if !enabled {
+      if let Ok(mut files) = self.files.lock() {
+        files.clear();
+      }
+    }
+  }
+
+  pub fn write(&self, key: &str, message: &str) -> Result<(), Error> {
+    if !self.enabled.load(Ordering::Relaxed) {
+      return Ok(());
+    }
+    let mut files = self
+      .files
+      .lock()
+      .map_err(|e| Error::LogFile(e.to_string()))?;
+
+    let needs_new_file = files
+      .get(key)
+      .map_or(true, |open_file| open_file.size >= MAX_LOG_FILE_SIZE);
+    if needs_new_file {
+      fs::create_dir_all(&self.dir)?;
+      let path = self.dir.join(log_file_name(key, unix_millis()));
+      let file = OpenOptions::new().create(true).append(true).open(path)?;
+      files.insert(key.to_string(), OpenLogFile { file, size: 0 });
+    }
+
+    let open_file = files.get_mut(key).unwrap();
+    let line = if message.ends_with('\n') {
+      message.to_string()
+    } else {
+      format!("{}\n", message)
+    };
+    open_file.file.write_all(line.as_bytes())?;
+    open_file.size += line.len() as u64;
+    Ok(())
+  }
+
+  /*
+   * Removes log files created more than retention_days ago. A retention period of 0 keeps all
+   * log files.
+   */
+  pub fn remove_expired(&self, retention_days: u32) -> Result<(), Error> {
+    if retention_days == 0 {
+      return Ok(());
+    }
+    let retention = Duration::from_secs(retention_days as u64 * 24 * 60 * 60);
+    let cutoff = unix_millis().saturating_sub(retention.as_millis() as u64);
+
+    for log_file in list_log_files_in(&self.dir)? {
+      if log_file.created_at < cutoff {
+        fs::remove_file(self.dir.join(&log_file.name))?;
+      }
+    }
+    Ok(())
+  }
+}
+
+fn log_file_name(key: &str, created_at: u64) -> String {
+  format!("{}-{}.{}", key, created_at, LOG_FILE_EXTENSION)
+}
+
+fn parse_log_file_name(name: &str) -> Option<(String, u64)> {
+  let stem = name.strip_suffix(&format!(".{}", LOG_FILE_EXTENSION))?;
+  let (key, created_at) = stem.rsplit_once('-')?;
+  if key.is_empty() {
+    return None;
+  }
+  Some((key.to_string(), created_at.parse().ok()?))
+}
+
+fn list_log_files_in(dir: &Path) -> Result<Vec<LogFileInfo>, Error> {
+  let mut log_files = Vec::new();
+  if !dir.exists() {
+    return Ok(log_files);
+  }
+
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    if !entry.file_type()?.is_file() {
+      continue;
+    }
+    let name = match entry.file_name().into_string() {
+      Ok(name) => name,
+      Err(_) => continue,
+    };
+    if let Some((key, created_at)) = parse_log_file_name(&name) {
+      log_files.push(LogFileInfo {
+        name,
+        key,
+        created_at,
+        size: entry.metadata()?.len(),
+      });
+    }
+  }
+  log_files.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+  Ok(log_files)
+}
+
+pub fn init_log_files(handle: &AppHandle) -> Result<(), Error> {
+  let (enabled, retention_days) = config::with_config(handle.clone(), |c| {
+    Ok((c.enable_error_log, c.log_retention_days))
+  })?;
+  let sink = LogFileSink::new(handle.path().app_log_dir()?, enabled);
+  if let Err(e) = sink.remove_expired(retention_days) {
+    eprintln!("Failed to remove expired log files: {}", e);
+  }
+  handle.manage(sink);
+  Ok(())
+}
+
+#[tauri::command(async)]
+pub fn list_log_files(handle: AppHandle) -> Result<Vec<LogFileInfo>, Error> {
+  list_log_files_in(&handle.path().app_log_dir()?)
+}
+
+#[tauri::command(async)]
+pub fn open_log_file(handle: AppHandle, name: String) -> Result<(), Error> {
+  if parse_log_file_name(&name).is_none() || name.contains(['/', '\\']) {
+    return Err(Error::LogFile(format!("{} is not a log file", name)));
+  }
+  let path = handle.path().app_log_dir()?.join(&name);
+  if !path.is_file() {
+    return Err(Error::LogFile(format!("{} does not exist", name)));
+  }
+
+  // The shell plugin only opens URLs, so the file is passed to the default program directly.
+  open::that(&path)?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_log_file_name_valid() {
+    assert_eq!(
+      Some(("run-nodecg".to_string(), 1700000000000)),
+      parse_log_file_name(&log_file_name("run-nodecg", 1700000000000))
+    );
+  }
+
+  #[test]
+  fn parse_log_file_name_invalid() {
+    assert_eq!(None, parse_log_file_name("run-nodecg.log"));
+    assert_eq!(None, parse_log_file_name("run-nodecg-1700000000000.txt"));
+    assert_eq!(None, parse_log_file_name("-1700000000000.log"));
+  }
+}
@@ -0,0 +1,140 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::error::Error;
+use crate::log::LogPayload;
+
+// How many log lines are kept for each log key. Older lines are dropped first.
+const LOG_HISTORY_CAPACITY: usize = 5000;
+const MAX_PAGE_SIZE: usize = 500;
+
+struct KeyHistory {
+  next_id: u64,
+  entries: VecDeque<LogPayload>,
+}
+
+/*
+ * Keeps the most recent log lines of every log key, so the UI can rebuild its logs after the
+ * webview is reloaded. Each line gets an ID that increases with every line logged under its key.
+ */
+#[derive(Default)]
+pub struct LogHistory {
+  keys: Mutex<HashMap<String, KeyHistory>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogHistoryPage {
+  pub entries: Vec<LogPayload>,
+  // Pass the ID of the last entry as `since` to get the next page.
+  pub has_more: bool,
+}
+
+impl LogHistory {
+  /*
+   * Stores a log line and returns the ID assigned to it.
+   */
+  pub fn record(&self, key: &str, mut payload: LogPayload) -> Result<u64, Error> {
+    let mut keys = self
+      .keys
+      .lock()
+      .map_err(|e| Error::LogHistory(e.to_string()))?;
+    let history = keys.entry(key.to_string()).or_insert_with(|| KeyHistory {
+      next_id: 1,
+      entries: VecDeque::new(),
+    });
+
+    let id = history.next_id;
+    history.next_id += 1;
+    payload.id = id;
+    if history.entries.len() >= LOG_HISTORY_CAPACITY {
+      history.entries.pop_front();
+    }
+    history.entries.push_back(payload);
+    Ok(id)
+  }
+
+  /*
+   * Returns up to limit lines logged after the line with the ID since, oldest first.
+   */
+  pub fn page(&self, key: &str, since: u64, limit: usize) -> Result<LogHistoryPage, Error> {
+    let keys = self
+      .keys
+      .lock()
+      .map_err(|e| Error::LogHistory(e.to_string()))?;
+    let history = match keys.get(key) {
+      Some(history) => history,
+      None => {
+        return Ok(LogHistoryPage {
+          entries: Vec::new(),
+          has_more: false,
+        })
+      }
+    };
+
+    let mut remaining = history.entries.iter().filter(|entry| entry.id > since);
+    let entries: Vec<LogPayload> = remaining.by_ref().take(limit).cloned().collect();
+    Ok(LogHistoryPage {
+      entries,
+      has_more: remaining.next().is_some(),
+    })
+  }
+
+  pub fn clear(&self, key: &str) -> Result<(), Error> {
+    let mut keys = self
+      .keys
+      .lock()
+      .map_err(|e| Error::LogHistory(e.to_string()))?;
+    if let Some(history) = keys.get_mut(key) {
+      history.entries.clear();
+    }
+    Ok(())
+  }
+}
+
+#[tauri::command]
+pub fn get_log_history(
+  history: tauri::State<LogHistory>,
+  key: String,
+  since: Option<u64>,
+  limit: Option<usize>,
+) -> Result<LogHistoryPage, Error> {
+  let limit = limit.unwrap_or(MAX_PAGE_SIZE).min(MAX_PAGE_SIZE);
+  history.page(&key, since.unwrap_or(0), limit)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn record_lines(history: &LogHistory, count: usize) {
+    for i in 0..count {
+      history
+        .record("test", LogPayload::new(&format!("Line {}", i), None))
+        .unwrap();
+    }
+  }
+
+  #[test]
+  fn page_returns_lines_after_since() {
+    let history = LogHistory::default();
+    record_lines(&history, 5);
+
+    let page = history.page("test", 2, 2).unwrap();
+    assert_eq!(
+      vec![3, 4],
+      page.entries.iter().map(|e| e.id).collect::<Vec<u64>>()
+    );
+    assert!(page.has_more);
+    assert!(!history.page("test", 4, 2).unwrap().has_more);
+  }
+
+  #[test]
+  fn record_drops_oldest_lines() {
+    let history = LogHistory::default();
+    record_lines(&history, LOG_HISTORY_CAPACITY + 1);
+
+    let page = history.page("test", 0, 1).unwrap();
+    assert_eq!(2, page.entries[0].id);
+  }
+}
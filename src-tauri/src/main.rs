@@ -3,17 +3,26 @@
   windows_subsystem = "windows"
 )]
 
-use crate::log::{err_to_string, format_error, LogEmitter};
+use crate::log::format_error;
 use tauri::{Manager, RunEvent};
 
 mod bundles;
 mod config;
 mod dependencies;
+mod drift;
 mod error;
 mod git;
 mod log;
+mod log_files;
+mod log_history;
+mod manifest;
 mod nodecg;
 mod npm;
+mod operations;
+mod profiles;
+mod snapshot;
+mod trash;
+mod util;
 
 use nodecg::ManagedNodecg;
 
@@ -70,7 +79,14 @@ fn main() {
     .plugin(tauri_plugin_dialog::init())
     .setup(|app| {
       app.manage(ManagedNodecg::new(app.handle().clone()));
+      app.manage(log_history::LogHistory::default());
+      app.manage(operations::OperationRegistry::default());
+      app.manage(bundles::UninstallConfirmations::default());
       config::check_config(app.handle().clone())?;
+      // Logs are still shown in the app if they cannot be written to files.
+      if let Err(e) = log_files::init_log_files(app.handle()) {
+        eprintln!("Failed to set up log files: {}", e);
+      }
 
       Ok(())
     })
@@ -78,14 +94,40 @@ fn main() {
       nodecg::install_nodecg,
       nodecg::start_nodecg,
       nodecg::stop_nodecg,
+      nodecg::get_nodecg_status,
+      nodecg::list_nodecg_instances,
+      nodecg::get_default_nodecg_install_dir,
+      nodecg::get_nodecg_health,
+      nodecg::get_nodecg_metrics_history,
+      nodecg::get_nodecg_supervisor_state,
+      nodecg::get_bundle_issue_counts,
       open_path_in_terminal,
       bundles::install_bundle,
       bundles::fetch_bundle_versions,
       bundles::set_bundle_version,
+      bundles::request_bundle_uninstall,
       bundles::uninstall_bundle,
       bundles::get_bundle_git_tag,
+      snapshot::get_bundle_snapshot,
+      snapshot::restore_bundle_snapshot,
+      manifest::export_installation_manifest,
+      manifest::import_installation_manifest,
+      drift::check_installation_drift,
+      drift::sync_installation_manifest,
       dependencies::get_nodejs_version,
       config::update_config,
+      config::take_config_reset,
+      profiles::create_profile,
+      profiles::rename_profile,
+      profiles::delete_profile,
+      profiles::activate_profile,
+      log_files::list_log_files,
+      log_files::open_log_file,
+      log_history::get_log_history,
+      operations::list_operations,
+      operations::cancel_operation,
+      trash::list_trashed_bundles,
+      trash::restore_trashed_bundle,
     ]);
 
   let app = builder
@@ -95,13 +137,9 @@ fn main() {
   app.run(|handle, e| match e {
     RunEvent::ExitRequested { api, .. } => {
       let managed_nodecg = handle.state::<ManagedNodecg>();
-      match managed_nodecg.stop() {
-        Ok(_) => {}
-        Err(e) => {
-          let logger = LogEmitter::new(&handle, "run-nodecg");
-          logger.emit_log(&err_to_string("Failed to shut down NodeCG", e));
-          api.prevent_exit();
-        }
+      // Failures are logged to the instance that could not be stopped
+      if managed_nodecg.stop_all().is_err() {
+        api.prevent_exit();
       }
     }
     _ => {}
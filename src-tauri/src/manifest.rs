@@ -0,0 +1,307 @@
+use std::fs;
+use std::path::Path;
+
+use tauri::AppHandle;
+use tauri_plugin_http::reqwest;
+use tauri_plugin_shell::ShellExt;
+
+use crate::bundles::{resolve_bundle_dir, validate_bundle_name};
+use crate::error::Error;
+use crate::git::{get_tag_name_at_head, try_open_repository};
+use crate::log::LogEmitter;
+use crate::operations::LockTarget;
+use crate::util::bundle_config_path;
+use crate::{config, git, nodecg, npm};
+
+const MANIFEST_VERSION: u32 = 1;
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstallationManifest {
+  pub manifest_version: u32,
+  pub nodecg_version: Option<String>,
+  pub bundles: Vec<BundleManifestEntry>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleManifestEntry {
+  pub name: String,
+  pub remote_url: Option<String>,
+  pub tag: Option<String>,
+  pub commit: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub config: Option<serde_json::Value>,
+}
+
+impl BundleManifestEntry {
+  /*
+   * The revision the bundle should be checked out at. Tags are preferred, as they are what
+   * NCGMGR shows to the user, but the commit is used if the bundle was not on a tag.
+   */
+  pub fn pinned_revision(&self) -> Option<&String> {
+    self.tag.as_ref().or(self.commit.as_ref())
+  }
+}
+
+fn read_bundle_manifest_entry(
+  install_dir: &str,
+  bundle_name: &str,
+  include_config: bool,
+) -> Result<BundleManifestEntry, Error> {
+  let bundle_dir = resolve_bundle_dir(install_dir, bundle_name)?;
+  let (remote_url, tag, commit) = match try_open_repository(&bundle_dir)? {
+    Some(repo) => {
+      let remote_url = git::get_remote(&repo)?.url().map(|url| url.to_string());
+      let commit = repo.head()?.peel_to_commit()?.id().to_string();
+      (remote_url, get_tag_name_at_head(&repo)?, Some(commit))
+    }
+    None => (None, None, None),
+  };
+
+  let config_path = bundle_config_path(install_dir, bundle_name);
+  let config = if include_config && config_path.exists() {
+    Some(serde_json::from_str(&fs::read_to_string(config_path)?)?)
+  } else {
+    None
+  };
+
+  Ok(BundleManifestEntry {
+    name: bundle_name.to_string(),
+    remote_url,
+    tag,
+    commit,
+    config,
+  })
+}
+
+pub fn read_installation_manifest(
+  install_dir: &str,
+  include_config: bool,
+) -> Result<InstallationManifest, Error> {
+  let mut bundles = Vec::new();
+  let bundles_dir = Path::new(install_dir).join("bundles");
+  if bundles_dir.exists() {
+    for entry in fs::read_dir(bundles_dir)? {
+      let entry = entry?;
+      if !entry.file_type()?.is_dir() {
+        continue;
+      }
+      // Directories that NCGMGR could not have installed a bundle into are left out.
+      let bundle_name = match entry.file_name().into_string() {
+        Ok(bundle_name) if validate_bundle_name(&bundle_name).is_ok() => bundle_name,
+        _ => continue,
+      };
+      bundles.push(read_bundle_manifest_entry(
+        install_dir,
+        &bundle_name,
+        include_config,
+      )?);
+    }
+  }
+  bundles.sort_by(|a, b| a.name.cmp(&b.name));
+
+  Ok(InstallationManifest {
+    manifest_version: MANIFEST_VERSION,
+    nodecg_version: nodecg::get_installed_nodecg_version(install_dir)?,
+    bundles,
+  })
+}
+
+/*
+ * Reads a manifest file and makes sure every bundle name in it is a valid directory name, as
+ * manifests may come from anywhere.
+ */
+pub fn read_manifest_file(path: &str) -> Result<InstallationManifest, Error> {
+  let manifest: InstallationManifest = serde_json::from_str(&fs::read_to_string(path)?)?;
+  if manifest.manifest_version > MANIFEST_VERSION {
+    return Err(Error::UnsupportedManifest(manifest.manifest_version));
+  }
+  for bundle in &manifest.bundles {
+    validate_bundle_name(&bundle.name)?;
+  }
+
+  Ok(manifest)
+}
+
+/*
+ * Clones a bundle listed in a manifest, checks out its pinned revision, installs its dependencies
+ * and writes its configuration file if the manifest contains one.
+ */
+pub async fn install_manifest_bundle(
+  handle: &AppHandle,
+  install_dir: &str,
+  bundle: &BundleManifestEntry,
+  logger: &LogEmitter,
+) -> Result<(), Error> {
+  let remote_url = bundle
+    .remote_url
+    .as_ref()
+    .ok_or_else(|| Error::ManifestBundleWithoutRemote(bundle.name.clone()))?;
+  let bundle_dir = resolve_bundle_dir(install_dir, &bundle.name)?;
+  fs::create_dir_all(Path::new(install_dir).join("bundles"))?;
+  let bundle_dir = bundle_dir.to_string_lossy().to_string();
+
+  logger.emit_log(&format!("Cloning {}...", remote_url));
+  {
+    let repo = git::clone_repository(remote_url, &bundle_dir, logger.cancellation())?;
+    if let Some(revision) = bundle.pinned_revision() {
+      logger.emit_log(&format!("Checking out {}...", revision));
+      git::checkout_version(&repo, revision.to_string())?;
+    }
+  }
+
+  write_manifest_bundle_config(install_dir, bundle)?;
+
+  npm::install_dependencies_and_wait(handle.shell(), &bundle_dir, logger).await?;
+  Ok(())
+}
+
+pub fn write_manifest_bundle_config(
+  install_dir: &str,
+  bundle: &BundleManifestEntry,
+) -> Result<(), Error> {
+  if let Some(config) = &bundle.config {
+    validate_bundle_name(&bundle.name)?;
+    fs::create_dir_all(Path::new(install_dir).join("cfg"))?;
+    fs::write(
+      bundle_config_path(install_dir, &bundle.name),
+      serde_json::to_string_pretty(config)?,
+    )?;
+  }
+
+  Ok(())
+}
+
+#[tauri::command(async)]
+pub fn export_installation_manifest(
+  handle: AppHandle,
+  path: String,
+  include_config: bool,
+) -> Result<(), Error> {
+  let install_dir = config::with_config(handle.clone(), |c| Ok(c.install_dir()))?
+    .ok_or(Error::MissingInstallDir)?;
+  let manifest = read_installation_manifest(&install_dir, include_config)?;
+
+  fs::write(path, serde_json::to_string_pretty(&manifest)?)?;
+  Ok(())
+}
+
+#[tauri::command]
+pub async fn import_installation_manifest(
+  handle: AppHandle,
+  path: String,
+  use_default_directory: bool,
+) -> Result<(), Error> {
+  let manifest = read_manifest_file(&path)?;
+  let logger = LogEmitter::start_operation(
+    &handle,
+    "import-manifest",
+    &format!("Import {}", path),
+    manifest.bundles.len() as u32 + 2,
+  );
+  let result = import_manifest(&handle, &manifest, use_default_directory, &logger).await;
+  logger.finish_operation(&result);
+  result
+}
+
+async fn import_manifest(
+  handle: &AppHandle,
+  manifest: &InstallationManifest,
+  use_default_directory: bool,
+  logger: &LogEmitter,
+) -> Result<(), Error> {
+  let bundle_count = manifest.bundles.len() as u32;
+  logger.emit_progress_stepped(0, "Importing installation...");
+
+  let profile = config::active_profile(handle.clone())?;
+  let install_dir = nodecg::get_target_install_dir(handle, &profile, use_default_directory)?;
+  let nodecg_pause = nodecg::pause_for_bundle_change(
+    handle,
+    LockTarget::Installation(install_dir.clone()),
+    "Import installation",
+  )
+  .await?;
+  logger.emit_log(&format!("NodeCG will be installed in {}", install_dir));
+
+  if use_default_directory {
+    logger.emit_log("Cleaning install directory...");
+    let install_dir_path = Path::new(&install_dir);
+    rm_rf::ensure_removed(install_dir_path)?;
+    fs::create_dir_all(install_dir_path)?;
+    nodecg_pause.lock().restore_lockfile()?;
+    config::update_install_dir(handle.clone(), install_dir.clone())?;
+  }
+
+  let client = reqwest::Client::builder().build()?;
+  let (nodecg_version, tarball_url) =
+    nodecg::resolve_nodecg_release(&client, manifest.nodecg_version.as_deref()).await?;
+  logger.emit_progress_stepped(1, &format!("Installing NodeCG {}...", nodecg_version));
+  let tarball = nodecg::download_nodecg_tarball(&client, &tarball_url, logger).await?;
+  nodecg::extract_nodecg_archive(&tarball, &install_dir, logger)?;
+  npm::install_dependencies_and_wait(handle.shell(), &install_dir, logger).await?;
+
+  for (i, bundle) in manifest.bundles.iter().enumerate() {
+    logger.check_cancelled()?;
+    logger.emit_progress_stepped(i as u32 + 2, &format!("Installing {}...", bundle.name));
+    install_manifest_bundle(handle, &install_dir, bundle, logger).await?;
+  }
+
+  logger.emit_progress_stepped(bundle_count + 2, "Done!");
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::util::test_util::TestDir;
+
+  #[test]
+  fn pinned_revision_prefers_tag() {
+    let entry = BundleManifestEntry {
+      name: "test-bundle".to_string(),
+      remote_url: Some("https://github.com/test-user/test-bundle.git".to_string()),
+      tag: Some("1.0.0".to_string()),
+      commit: Some("0123456789abcdef".to_string()),
+      config: None,
+    };
+    assert_eq!(Some(&"1.0.0".to_string()), entry.pinned_revision());
+  }
+
+  #[test]
+  fn pinned_revision_without_tag() {
+    let entry = BundleManifestEntry {
+      name: "test-bundle".to_string(),
+      remote_url: Some("https://github.com/test-user/test-bundle.git".to_string()),
+      tag: None,
+      commit: Some("0123456789abcdef".to_string()),
+      config: None,
+    };
+    assert_eq!(
+      Some(&"0123456789abcdef".to_string()),
+      entry.pinned_revision()
+    );
+  }
+
+  #[test]
+  fn read_manifest_file_rejects_invalid_bundle_names() {
+    let dir = TestDir::new("manifest-invalid-name");
+    dir.write(
+      "manifest.json",
+      r#"{
+        "manifestVersion": 1,
+        "nodecgVersion": "2.2.0",
+        "bundles": [{ "name": "../../x", "remoteUrl": null, "tag": null, "commit": null }]
+      }"#,
+    );
+    let path = dir.path().join("manifest.json");
+
+    assert_eq!(
+      "\"../../x\" is not a valid bundle name.",
+      read_manifest_file(&path.to_string_lossy())
+        .err()
+        .unwrap()
+        .to_string()
+    );
+  }
+}
@@ -1,113 +1,1168 @@
+use std::collections::{HashMap, VecDeque};
 use std::fs;
+use std::net::TcpListener;
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use flate2::read::GzDecoder;
-use sysinfo::{Pid, ProcessRefreshKind, RefreshKind, System};
+use sysinfo::{Pid, ProcessRefreshKind, RefreshKind, System, UpdateKind};
 use tar::Archive;
+use tauri::async_runtime::spawn;
 use tauri::{AppHandle, Manager};
 use tauri_plugin_http::reqwest;
 use tauri_plugin_shell::process::CommandChild;
 use tauri_plugin_shell::ShellExt;
 
-use crate::{config, npm};
+use crate::config::{BundleChangePolicy, LaunchSettings, NodecgProfile};
 use crate::error::Error;
-use crate::log::{emit_tauri_process_output, LogEmitter};
+use crate::log::{
+  emit_event, emit_tauri_process_output_with, err_to_string, LogEmitter, LogLevel, LogPayload,
+  ProcessResult,
+};
+use crate::log_history::LogHistory;
 use crate::npm::NPMPackageMetadata;
+use crate::operations::{LockTarget, OperationLock, OperationRegistry};
+use crate::util::{is_process_alive, unix_millis};
+use crate::{config, npm, operations};
 
-#[derive(Clone, serde::Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
 pub enum NodecgStatus {
+  // NodeCG has not been started since NCGMGR was launched.
   NotRunning,
-  Running
+  // The process has been spawned, but the dashboard is not responding yet.
+  Starting,
+  Ready,
+  // The process is still running, but the dashboard stopped responding.
+  Unhealthy,
+  Stopping,
+  // NodeCG exited normally or was stopped by the user.
+  Stopped,
+  Crashed,
+  // NodeCG crashed and will be started again after a delay.
+  Restarting,
 }
 
-pub struct ManagedNodecg {
+const DEFAULT_NODECG_PORT: u16 = 9090;
+const READINESS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+// How many health checks in a row have to fail before NodeCG is considered unhealthy.
+const UNHEALTHY_AFTER_FAILURES: u32 = 3;
+
+// Restart delays double with every consecutive crash, starting at the base delay.
+const RESTART_BASE_DELAY: Duration = Duration::from_secs(1);
+const RESTART_MAX_DELAY: Duration = Duration::from_secs(60);
+// If NodeCG stays up for this long, earlier crashes no longer count towards the crash-loop limit.
+const CRASH_LOOP_RESET_AFTER: Duration = Duration::from_secs(60);
+
+const METRICS_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+// One hour of samples at the interval above.
+const METRICS_HISTORY_LENGTH: usize = 720;
+
+// How long a tarball download waits for data before checking whether it has been cancelled
+const DOWNLOAD_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SupervisorState {
+  pub restart_count: u32,
+  pub consecutive_crashes: u32,
+  pub last_exit_code: Option<i32>,
+  #[serde(skip)]
+  last_started_at: Option<Instant>,
+  #[serde(skip)]
+  restart_pending: bool,
+}
+
+#[derive(Clone, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodecgHealth {
+  pub healthy: bool,
+  pub latency_ms: Option<u64>,
+  pub consecutive_failures: u32,
+}
+
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodecgMetrics {
+  pub pid: u32,
+  // Milliseconds since the Unix epoch
+  pub timestamp: u64,
+  // Percentage of a single CPU core, so this may exceed 100 on multi-core systems.
+  pub cpu_usage: f32,
+  // Resident memory in bytes
+  pub memory: u64,
+  pub uptime_secs: u64,
+  pub child_process_count: usize,
+}
+
+#[derive(Clone, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleIssueCount {
+  pub bundle: String,
+  pub errors: u32,
+  pub warnings: u32,
+}
+
+// Events about a NodeCG instance carry the profile the instance belongs to.
+#[derive(Clone, serde::Serialize)]
+pub struct InstanceEvent<T> {
+  pub profile: String,
+  #[serde(flatten)]
+  pub data: T,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct NodecgStatusChange {
+  pub profile: String,
+  pub status: NodecgStatus,
+}
+
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodecgInstanceInfo {
+  pub profile: String,
+  pub status: NodecgStatus,
+  pub pid: Option<u32>,
+  pub port: Option<u16>,
+  pub log_key: String,
+}
+
+/*
+ * Returns the log key of the NodeCG instance running from a profile. Tauri only allows a few
+ * characters in event names, so other characters are replaced by an underscore and their character
+ * code. Underscores are escaped the same way to keep keys unique.
+ */
+pub fn run_log_key(profile: &str) -> String {
+  let mut key = "run-nodecg-".to_string();
+  for c in profile.chars() {
+    if c.is_ascii_alphanumeric() || c == '-' {
+      key.push(c);
+    } else {
+      key.push_str(&format!("_{:x}_", c as u32));
+    }
+  }
+  key
+}
+
+/*
+ * A NodeCG process started from a profile, which is restarted by the same instance after crashes.
+ */
+pub struct NodecgInstance {
+  profile: String,
   process: Mutex<Option<CommandChild>>,
+  status: Mutex<NodecgStatus>,
+  health: Mutex<NodecgHealth>,
+  metrics: Mutex<VecDeque<NodecgMetrics>>,
+  bundle_issues: Mutex<HashMap<String, BundleIssueCount>>,
+  supervisor: Mutex<SupervisorState>,
+  // The port NodeCG listens on while it is running. Used to keep instances off each other's ports.
+  port: Mutex<Option<u16>>,
   app_handle: AppHandle,
 }
 
-impl ManagedNodecg {
-  pub fn new(app_handle: AppHandle) -> Self {
-    ManagedNodecg {
+impl NodecgInstance {
+  fn new(app_handle: AppHandle, profile: &str) -> Self {
+    NodecgInstance {
+      profile: profile.to_string(),
       process: Mutex::new(None),
+      status: Mutex::new(NodecgStatus::NotRunning),
+      health: Mutex::new(NodecgHealth::default()),
+      metrics: Mutex::new(VecDeque::new()),
+      bundle_issues: Mutex::new(HashMap::new()),
+      supervisor: Mutex::new(SupervisorState::default()),
+      port: Mutex::new(None),
       app_handle,
     }
   }
 
-  pub fn start(&self) -> Result<(), Error> {
-    let nodecg_path = config::with_config(self.app_handle.clone(), |c| Ok(c.nodecg_install_dir))?
-      .ok_or(Error::MissingInstallDir)?;
+  pub fn status(&self) -> Result<NodecgStatus, Error> {
+    self
+      .status
+      .lock()
+      .map(|status| *status)
+      .map_err(|e| Error::NodeCGStatus(e.to_string()))
+  }
+
+  pub fn is_running(&self) -> Result<bool, Error> {
+    Ok(matches!(
+      self.status()?,
+      NodecgStatus::Starting
+        | NodecgStatus::Ready
+        | NodecgStatus::Unhealthy
+        | NodecgStatus::Stopping
+        | NodecgStatus::Restarting
+    ))
+  }
+
+  pub fn port(&self) -> Option<u16> {
+    self.port.lock().ok().and_then(|port| *port)
+  }
+
+  fn release_port(&self) {
+    if let Ok(mut port) = self.port.lock() {
+      *port = None;
+    }
+  }
+
+  pub fn log_key(&self) -> String {
+    run_log_key(&self.profile)
+  }
+
+  pub fn info(&self) -> Result<NodecgInstanceInfo, Error> {
+    Ok(NodecgInstanceInfo {
+      profile: self.profile.clone(),
+      status: self.status()?,
+      pid: self.current_pid(),
+      port: self.port(),
+      log_key: self.log_key(),
+    })
+  }
+
+  fn set_status(&self, status: NodecgStatus) -> Result<(), Error> {
+    {
+      let mut current_status = self
+        .status
+        .lock()
+        .map_err(|e| Error::NodeCGStatus(e.to_string()))?;
+      *current_status = status;
+    }
+    if matches!(status, NodecgStatus::Stopped | NodecgStatus::Crashed) {
+      self.release_port();
+    }
+
+    emit_event(
+      &self.app_handle,
+      "nodecg-status-change",
+      NodecgStatusChange {
+        profile: self.profile.clone(),
+        status,
+      },
+    );
+    Ok(())
+  }
+
+  fn current_pid(&self) -> Option<u32> {
+    self
+      .process
+      .lock()
+      .ok()
+      .and_then(|process| process.as_ref().map(|child| child.pid()))
+  }
+
+  pub fn health(&self) -> Result<NodecgHealth, Error> {
+    self
+      .health
+      .lock()
+      .map(|health| health.clone())
+      .map_err(|e| Error::NodeCGStatus(e.to_string()))
+  }
+
+  /*
+   * Updates NodeCG's health with the result of a health check and moves between the Starting,
+   * Ready and Unhealthy states accordingly. Results for processes that have exited or been
+   * replaced in the meantime are ignored.
+   */
+  fn record_health_check(&self, pid: u32, latency: Option<Duration>) -> Result<(), Error> {
+    if self.current_pid() != Some(pid) {
+      return Ok(());
+    }
+    let status = self.status()?;
+    if status == NodecgStatus::Starting && latency.is_none() {
+      return Ok(());
+    }
+
+    let health = {
+      let mut health = self
+        .health
+        .lock()
+        .map_err(|e| Error::NodeCGStatus(e.to_string()))?;
+      match latency {
+        Some(latency) => {
+          health.healthy = true;
+          health.latency_ms = Some(latency.as_millis() as u64);
+          health.consecutive_failures = 0;
+        }
+        None => {
+          health.latency_ms = None;
+          health.consecutive_failures += 1;
+          if health.consecutive_failures >= UNHEALTHY_AFTER_FAILURES {
+            health.healthy = false;
+          }
+        }
+      }
+      health.clone()
+    };
+    emit_event(
+      &self.app_handle,
+      "nodecg-health",
+      InstanceEvent {
+        profile: self.profile.clone(),
+        data: health.clone(),
+      },
+    );
+
+    match status {
+      NodecgStatus::Starting | NodecgStatus::Unhealthy if health.healthy => {
+        self.set_status(NodecgStatus::Ready)
+      }
+      NodecgStatus::Ready if !health.healthy => {
+        LogEmitter::new(&self.app_handle, &self.log_key()).emit_log(&format!(
+          "NodeCG has not responded to the last {} health checks.",
+          health.consecutive_failures
+        ));
+        self.set_status(NodecgStatus::Unhealthy)
+      }
+      _ => Ok(()),
+    }
+  }
+
+  /*
+   * Returns the collected resource usage samples, oldest first. The history is kept across
+   * automatic restarts and cleared when NodeCG is started by the user.
+   */
+  pub fn metrics_history(&self) -> Result<Vec<NodecgMetrics>, Error> {
+    self
+      .metrics
+      .lock()
+      .map(|metrics| metrics.iter().cloned().collect())
+      .map_err(|e| Error::NodeCGStatus(e.to_string()))
+  }
+
+  fn record_metrics(&self, metrics: NodecgMetrics) -> Result<(), Error> {
+    if self.current_pid() != Some(metrics.pid) {
+      return Ok(());
+    }
+
+    {
+      let mut history = self
+        .metrics
+        .lock()
+        .map_err(|e| Error::NodeCGStatus(e.to_string()))?;
+      if history.len() >= METRICS_HISTORY_LENGTH {
+        history.pop_front();
+      }
+      history.push_back(metrics.clone());
+    }
+
+    emit_event(
+      &self.app_handle,
+      "nodecg-metrics",
+      InstanceEvent {
+        profile: self.profile.clone(),
+        data: metrics,
+      },
+    );
+    Ok(())
+  }
+
+  /*
+   * Counts errors and warnings logged by each bundle, so the UI can point out misbehaving
+   * bundles.
+   */
+  fn record_log_line(&self, payload: &LogPayload) -> Result<(), Error> {
+    let parsed = match &payload.parsed {
+      Some(parsed) => parsed,
+      None => return Ok(()),
+    };
+    let bundle = match &parsed.bundle {
+      Some(bundle) if parsed.level == LogLevel::Error || parsed.level == LogLevel::Warn => bundle,
+      _ => return Ok(()),
+    };
+
+    let count = {
+      let mut bundle_issues = self
+        .bundle_issues
+        .lock()
+        .map_err(|e| Error::NodeCGStatus(e.to_string()))?;
+      let count = bundle_issues
+        .entry(bundle.clone())
+        .or_insert_with(|| BundleIssueCount {
+          bundle: bundle.clone(),
+          ..Default::default()
+        });
+      if parsed.level == LogLevel::Error {
+        count.errors += 1;
+      } else {
+        count.warnings += 1;
+      }
+      count.clone()
+    };
+
+    emit_event(
+      &self.app_handle,
+      "nodecg-bundle-issues",
+      InstanceEvent {
+        profile: self.profile.clone(),
+        data: count,
+      },
+    );
+    Ok(())
+  }
+
+  pub fn bundle_issue_counts(&self) -> Result<Vec<BundleIssueCount>, Error> {
+    let mut counts: Vec<BundleIssueCount> = self
+      .bundle_issues
+      .lock()
+      .map_err(|e| Error::NodeCGStatus(e.to_string()))?
+      .values()
+      .cloned()
+      .collect();
+    counts.sort_by(|a, b| a.bundle.cmp(&b.bundle));
+    Ok(counts)
+  }
+
+  pub fn start(self: &Arc<Self>) -> Result<(), Error> {
+    if self.is_running()? {
+      return Err(Error::NodeCGLaunch(
+        "NodeCG is already running.".to_string(),
+      ));
+    }
+    {
+      let mut supervisor = self
+        .supervisor
+        .lock()
+        .map_err(|e| Error::NodeCGLaunch(e.to_string()))?;
+      *supervisor = SupervisorState::default();
+    }
+    if let Ok(mut metrics) = self.metrics.lock() {
+      metrics.clear();
+    }
+    if let Ok(mut bundle_issues) = self.bundle_issues.lock() {
+      bundle_issues.clear();
+    }
+    let _ = self.app_handle.state::<LogHistory>().clear(&self.log_key());
+
+    self.spawn_process()
+  }
+
+  fn spawn_process(self: &Arc<Self>) -> Result<(), Error> {
+    let profile = config::with_config(self.app_handle.clone(), |c| {
+      Ok(c.profile(&self.profile).cloned())
+    })?
+    .ok_or_else(|| Error::MissingProfile(self.profile.clone()))?;
+    let nodecg_path = profile.install_dir.ok_or(Error::MissingInstallDir)?;
+    let launch_settings = profile.launch_settings;
     let mut lock = self
       .process
       .lock()
       .map_err(|e| Error::NodeCGLaunch(e.to_string()))?;
-    let sys = System::new_with_specifics(RefreshKind::new().with_processes(ProcessRefreshKind::new()));
+    let sys = System::new_with_specifics(
+      RefreshKind::new().with_processes(ProcessRefreshKind::new().with_cwd(UpdateKind::Always)),
+    );
 
-    if lock.is_some() && sys.process(Pid::from_u32(lock.as_ref().unwrap().pid())).is_some() {
+    if lock.is_some()
+      && sys
+        .process(Pid::from_u32(lock.as_ref().unwrap().pid()))
+        .is_some()
+    {
       return Err(Error::NodeCGLaunch(
         "NodeCG is already running.".to_string(),
       ));
     }
 
-    let logger = LogEmitter::new(&self.app_handle, "run-nodecg");
+    self
+      .app_handle
+      .state::<OperationRegistry>()
+      .ensure_unlocked(&nodecg_path)?;
+    if let Some((pid, name)) = find_external_nodecg(&sys, &nodecg_path) {
+      return Err(Error::NodeCGRunningExternally(pid.as_u32(), name));
+    }
+    let port = get_nodecg_port(&nodecg_path);
+    check_port_available(port)?;
+
+    let logger = LogEmitter::new(&self.app_handle, &self.log_key());
     let shell = self.app_handle.shell();
+    let node_program = match &launch_settings.node_path {
+      Some(node_path) if !Path::new(node_path).is_file() => {
+        return Err(Error::NodeCGLaunch(format!(
+          "Node executable not found at {}",
+          node_path
+        )));
+      }
+      Some(node_path) => node_path.clone(),
+      None => "node".to_string(),
+    };
+    self
+      .app_handle
+      .state::<ManagedNodecg>()
+      .reserve_port(&self.profile, port)?;
     let child = shell
-      .command("node")
-      .args([format!("{}/index.js", nodecg_path)])
-      .current_dir(PathBuf::from(nodecg_path))
-      .spawn()?;
+      .command(node_program)
+      .args(nodecg_launch_args(&launch_settings, &nodecg_path))
+      .envs(launch_settings.env)
+      .current_dir(PathBuf::from(&nodecg_path))
+      .spawn()
+      .map_err(|e| {
+        self.release_port();
+        Error::from(e)
+      })?;
 
+    let pid = child.1.pid();
     *lock = Some(child.1);
-    emit_tauri_process_output(&logger, child.0);
+    let line_instance = Arc::clone(self);
+    let output = emit_tauri_process_output_with(&logger, child.0, move |payload| {
+      let _ = line_instance.record_log_line(payload);
+    });
+    if let Ok(mut supervisor) = self.supervisor.lock() {
+      supervisor.last_started_at = Some(Instant::now());
+    }
+    if let Ok(mut health) = self.health.lock() {
+      *health = NodecgHealth::default();
+    }
+
+    let nodecg = Arc::clone(self);
+    spawn(async move {
+      let result = output.await.ok().flatten();
+      if let Some(delay) = nodecg.handle_exit(pid, result) {
+        tokio::time::sleep(delay).await;
+        nodecg.restart_after_crash();
+      }
+    });
 
-    self.app_handle.emit("nodecg-status-change", NodecgStatus::Running)?;
+    self.set_status(NodecgStatus::Starting)?;
+    spawn(monitor_health(Arc::clone(self), pid, port));
+    spawn(sample_metrics(Arc::clone(self), pid));
     Ok(())
   }
 
+  /*
+   * Called when the NodeCG process exits. Exits caused by stop() are ignored. If NodeCG crashed
+   * and should be restarted, returns how long to wait before doing so.
+   */
+  fn handle_exit(&self, pid: u32, result: Option<ProcessResult>) -> Option<Duration> {
+    {
+      let mut process = self.process.lock().ok()?;
+      match process.as_ref() {
+        Some(child) if child.pid() == pid => *process = None,
+        _ => return None,
+      }
+    }
+
+    let logger = LogEmitter::new(&self.app_handle, &self.log_key());
+    let (auto_restart, max_restart_attempts) = config::with_config(self.app_handle.clone(), |c| {
+      Ok((c.auto_restart, c.max_restart_attempts))
+    })
+    .unwrap_or((false, 0));
+    let mut supervisor = self.supervisor.lock().ok()?;
+    let exit_code = result.and_then(|r| r.code);
+    supervisor.last_exit_code = exit_code;
+
+    if exit_code == Some(0) {
+      let _ = self.set_status(NodecgStatus::Stopped);
+      return None;
+    }
+
+    if supervisor.last_started_at.map_or(false, |started_at| {
+      started_at.elapsed() >= CRASH_LOOP_RESET_AFTER
+    }) {
+      supervisor.consecutive_crashes = 0;
+    }
+    supervisor.consecutive_crashes += 1;
+    let _ = self.set_status(NodecgStatus::Crashed);
+
+    if !auto_restart {
+      return None;
+    }
+    if supervisor.consecutive_crashes > max_restart_attempts {
+      logger.emit_log(&format!(
+        "NodeCG crashed {} times in a row. Giving up on restarting it.",
+        supervisor.consecutive_crashes
+      ));
+      return None;
+    }
+
+    let delay = restart_delay(supervisor.consecutive_crashes);
+    supervisor.restart_pending = true;
+    logger.emit_log(&format!(
+      "NodeCG crashed. Restarting in {} seconds (attempt {} of {})...",
+      delay.as_secs(),
+      supervisor.consecutive_crashes,
+      max_restart_attempts
+    ));
+    let _ = self.set_status(NodecgStatus::Restarting);
+    emit_event(
+      &self.app_handle,
+      "nodecg-supervisor",
+      InstanceEvent {
+        profile: self.profile.clone(),
+        data: supervisor.clone(),
+      },
+    );
+    Some(delay)
+  }
+
+  fn restart_after_crash(self: &Arc<Self>) {
+    {
+      let mut supervisor = match self.supervisor.lock() {
+        Ok(supervisor) => supervisor,
+        Err(_) => return,
+      };
+      // stop() was called while waiting to restart
+      if !supervisor.restart_pending {
+        return;
+      }
+      supervisor.restart_pending = false;
+      supervisor.restart_count += 1;
+    }
+
+    if let Err(e) = self.spawn_process() {
+      let logger = LogEmitter::new(&self.app_handle, &self.log_key());
+      logger.emit_log(&err_to_string("Failed to restart NodeCG", e));
+      let _ = self.set_status(NodecgStatus::Crashed);
+    }
+  }
+
+  pub fn get_supervisor_state(&self) -> Result<SupervisorState, Error> {
+    self
+      .supervisor
+      .lock()
+      .map(|supervisor| supervisor.clone())
+      .map_err(|e| Error::NodeCGStatus(e.to_string()))
+  }
+
+  /*
+   * Asks NodeCG to shut down and waits for the configured grace period before killing it, giving
+   * it a chance to finish writing replicant data to disk.
+   */
   pub fn stop(&self) -> Result<(), Error> {
-    let mut process = self.process.lock()
+    let grace_period = config::with_config(self.app_handle.clone(), |c| {
+      Ok(c.shutdown_grace_period_secs)
+    })?;
+    // The process is no longer tracked once taken, so exits during the shutdown are left to stop().
+    let child = {
+      let mut process = self
+        .process
+        .lock()
         .map_err(|e| Error::NodeCGStop(e.to_string()))?;
 
-    if process.is_none() {
-      return Ok(());
+      let restart_pending = match self.supervisor.lock() {
+        Ok(mut supervisor) => std::mem::replace(&mut supervisor.restart_pending, false),
+        Err(_) => false,
+      };
+
+      match process.take() {
+        Some(child) => child,
+        None => {
+          if restart_pending {
+            self.set_status(NodecgStatus::Stopped)?;
+          }
+          return Ok(());
+        }
+      }
+    };
+    self.set_status(NodecgStatus::Stopping)?;
+
+    let logger = LogEmitter::new(&self.app_handle, &self.log_key());
+    let pid = Pid::from_u32(child.pid());
+
+    logger.emit_log("Stopping NodeCG...");
+    let exited = if request_shutdown(pid) {
+      logger.emit_log(&format!(
+        "Waiting up to {} seconds for NodeCG to exit...",
+        grace_period
+      ));
+      wait_for_exit(pid, Duration::from_secs(grace_period))
+    } else {
+      logger.emit_log("Could not ask NodeCG to shut down.");
+      false
+    };
+
+    if exited {
+      logger.emit_log("NodeCG has exited.");
+    } else {
+      logger.emit_log("NodeCG did not exit in time, killing it...");
+      child.kill()?;
+      if !wait_for_exit(pid, FORCED_EXIT_TIMEOUT) {
+        return Err(Error::NodeCGStop(format!(
+          "Process {} is still running after being killed",
+          pid
+        )));
+      }
     }
 
-    process.take().unwrap().kill()?;
-    *process = None;
+    self.set_status(NodecgStatus::Stopped)?;
 
-    self.app_handle.emit("nodecg-status-change", NodecgStatus::NotRunning)?;
+    Ok(())
+  }
+}
 
+/*
+ * Manages one NodeCG instance per profile, so multiple installations can run side by side.
+ */
+pub struct ManagedNodecg {
+  instances: Mutex<HashMap<String, Arc<NodecgInstance>>>,
+  app_handle: AppHandle,
+}
+
+impl ManagedNodecg {
+  pub fn new(app_handle: AppHandle) -> Self {
+    ManagedNodecg {
+      instances: Mutex::new(HashMap::new()),
+      app_handle,
+    }
+  }
+
+  /*
+   * Returns the instance of a profile, or None if NodeCG has not been started from it yet.
+   */
+  pub fn get(&self, profile: &str) -> Result<Option<Arc<NodecgInstance>>, Error> {
+    self
+      .instances
+      .lock()
+      .map(|instances| instances.get(profile).cloned())
+      .map_err(|e| Error::NodeCGStatus(e.to_string()))
+  }
+
+  fn all(&self) -> Result<Vec<Arc<NodecgInstance>>, Error> {
+    self
+      .instances
+      .lock()
+      .map(|instances| instances.values().cloned().collect())
+      .map_err(|e| Error::NodeCGStatus(e.to_string()))
+  }
+
+  pub fn start(&self, profile: &str) -> Result<(), Error> {
+    let exists = config::with_config(self.app_handle.clone(), |c| {
+      Ok(c.profile(profile).is_some())
+    })?;
+    if !exists {
+      return Err(Error::MissingProfile(profile.to_string()));
+    }
+
+    let instance = self
+      .instances
+      .lock()
+      .map_err(|e| Error::NodeCGLaunch(e.to_string()))?
+      .entry(profile.to_string())
+      .or_insert_with(|| Arc::new(NodecgInstance::new(self.app_handle.clone(), profile)))
+      .clone();
+    instance.start()
+  }
+
+  pub fn stop(&self, profile: &str) -> Result<(), Error> {
+    match self.get(profile)? {
+      Some(instance) => instance.stop(),
+      None => Ok(()),
+    }
+  }
+
+  /*
+   * Stops every instance at the same time, so shutting down takes at most one grace period. An
+   * instance failing to stop does not keep the others running. Returns the first error.
+   */
+  pub fn stop_all(&self) -> Result<(), Error> {
+    let stopping: Vec<_> = self
+      .all()?
+      .into_iter()
+      .map(|instance| {
+        let stopping_instance = Arc::clone(&instance);
+        (instance, thread::spawn(move || stopping_instance.stop()))
+      })
+      .collect();
+
+    let mut result = Ok(());
+    for (instance, stop) in stopping {
+      let stop_result = stop.join().unwrap_or_else(|_| {
+        Err(Error::NodeCGStop(
+          "The shutdown thread panicked".to_string(),
+        ))
+      });
+      if let Err(e) = stop_result {
+        let logger = LogEmitter::new(&self.app_handle, &instance.log_key());
+        logger.emit_log(&err_to_string("Failed to shut down NodeCG", &e));
+        if result.is_ok() {
+          result = Err(e);
+        }
+      }
+    }
+    result
+  }
+
+  pub fn is_running(&self, profile: &str) -> Result<bool, Error> {
+    match self.get(profile)? {
+      Some(instance) => instance.is_running(),
+      None => Ok(false),
+    }
+  }
+
+  /*
+   * Forgets the instance of a profile that is renamed or deleted. Its process must not be running.
+   */
+  pub fn remove(&self, profile: &str) -> Result<(), Error> {
+    if self.is_running(profile)? {
+      return Err(Error::ProfileRunning(profile.to_string()));
+    }
+    self
+      .instances
+      .lock()
+      .map_err(|e| Error::NodeCGStatus(e.to_string()))?
+      .remove(profile);
+    Ok(())
+  }
+
+  pub fn list(&self) -> Result<Vec<NodecgInstanceInfo>, Error> {
+    let mut instances = self
+      .all()?
+      .iter()
+      .map(|instance| instance.info())
+      .collect::<Result<Vec<NodecgInstanceInfo>, Error>>()?;
+    instances.sort_by(|a, b| a.profile.cmp(&b.profile));
+    Ok(instances)
+  }
+
+  /*
+   * Claims a port for the instance of a profile. Fails if another instance uses the port, even if
+   * that instance has not started listening on it yet.
+   */
+  fn reserve_port(&self, profile: &str, port: u16) -> Result<(), Error> {
+    let instances = self
+      .instances
+      .lock()
+      .map_err(|e| Error::NodeCGLaunch(e.to_string()))?;
+    if let Some(other) = instances
+      .values()
+      .find(|instance| instance.profile != profile && instance.port() == Some(port))
+    {
+      return Err(Error::PortUsedByProfile(port, other.profile.clone()));
+    }
+
+    let instance = instances
+      .get(profile)
+      .ok_or_else(|| Error::MissingProfile(profile.to_string()))?;
+    let mut instance_port = instance
+      .port
+      .lock()
+      .map_err(|e| Error::NodeCGLaunch(e.to_string()))?;
+    *instance_port = Some(port);
     Ok(())
   }
 }
 
-#[tauri::command]
-pub async fn install_nodecg(handle: AppHandle, use_default_directory: bool) -> Result<(), Error> {
-  let logger = LogEmitter::stepped(&handle, "install-nodecg", 4);
-  logger.emit_progress_stepped(0, "Installing NodeCG...");
+fn nodecg_launch_args(settings: &LaunchSettings, install_dir: &str) -> Vec<String> {
+  let mut args = settings.node_args.clone();
+  args.push(format!("{}/index.js", install_dir));
+  args.extend(settings.nodecg_args.iter().cloned());
+  args
+}
 
-  let install_dir = if use_default_directory {
-    handle.path().app_local_data_dir()
-      .map_err(|_| Error::CannotCreateDefaultInstallDir)?
-      .join("nodecg")
-      .to_str()
-      .ok_or(Error::CannotCreateDefaultInstallDir)?
-      .to_string()
+/*
+ * Holds the operation lock of a command that modifies the active profile's installation. If
+ * NodeCG was stopped for the command, it is restarted once the guard is dropped, after the lock
+ * has been released.
+ * This is intentionally done whether or not the command succeeded: Failed changes are rolled back
+ * or leave the rest of the installation usable, and the user expects NodeCG to keep running like
+ * it did before the command.
+ */
+pub struct NodecgPause {
+  app_handle: AppHandle,
+  lock: OperationLock,
+  // The profile to start NodeCG from again
+  restart_profile: Option<String>,
+}
+
+impl NodecgPause {
+  pub fn lock(&self) -> &OperationLock {
+    &self.lock
+  }
+}
+
+impl Drop for NodecgPause {
+  fn drop(&mut self) {
+    let profile = match &self.restart_profile {
+      Some(profile) => profile,
+      None => return,
+    };
+    // NodeCG cannot start while its installation is locked.
+    self.lock.release();
+    let logger = LogEmitter::new(&self.app_handle, &run_log_key(profile));
+    logger.emit_log("Restarting NodeCG...");
+    if let Err(e) = self.app_handle.state::<ManagedNodecg>().start(profile) {
+      // The command's operation may already be finished, so the failure is reported to the
+      // NodeCG log of the profile instead.
+      let message = err_to_string("Failed to restart NodeCG", e);
+      eprintln!("{}", message);
+      logger.emit_log(&message);
+    }
+  }
+}
+
+/*
+ * Locks the target for a command that modifies the active profile's installation and makes sure
+ * NodeCG is not running from it. Depending on the user's preference, NodeCG is either stopped and
+ * restarted once the returned guard is dropped, or the command is refused. NodeCG is only stopped
+ * once the lock has been acquired, so a command that cannot get the lock never interrupts it.
+ * NodeCG may keep running from other profiles.
+ */
+pub async fn pause_for_bundle_change(
+  handle: &AppHandle,
+  target: LockTarget,
+  description: &str,
+) -> Result<NodecgPause, Error> {
+  let (active_profile, bundle_change_policy) = config::with_config(handle.clone(), |c| {
+    Ok((c.active_profile, c.bundle_change_policy))
+  })?;
+  // NodeCG cannot be started while the lock is held, so it is checked for afterwards.
+  let lock = operations::lock(handle, target, description)?;
+  let restart_profile = if !handle
+    .state::<ManagedNodecg>()
+    .is_running(&active_profile)?
+  {
+    None
+  } else if bundle_change_policy == BundleChangePolicy::Refuse {
+    return Err(Error::NodeCGRunning);
   } else {
-    config::with_config(handle.clone(), |c| Ok(c.nodecg_install_dir))?.ok_or(Error::MissingInstallDir)?
+    // Waiting for NodeCG to exit can take up to the shutdown grace period.
+    let stop_handle = handle.clone();
+    let profile = active_profile.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+      stop_handle.state::<ManagedNodecg>().stop(&profile)
+    })
+    .await??;
+    Some(active_profile)
   };
-  logger.emit_log(&format!("NodeCG will be installed in {}", install_dir));
+  Ok(NodecgPause {
+    app_handle: handle.clone(),
+    lock,
+    restart_profile,
+  })
+}
 
-  if use_default_directory {
-    logger.emit_log("Cleaning install directory...");
-    let install_dir_path = Path::new(&install_dir);
-    rm_rf::ensure_removed(install_dir_path)?;
-    fs::create_dir_all(install_dir_path)?;
-    config::update_install_dir(handle.clone(), install_dir.clone())?;
+/*
+ * Checks whether the NodeCG dashboard responds and how long it takes to do so. Redirects are
+ * followed, so an error status means NodeCG is listening but not able to serve the dashboard.
+ */
+pub async fn probe_nodecg(client: &reqwest::Client, port: u16) -> Result<Duration, reqwest::Error> {
+  let start = Instant::now();
+  client
+    .get(format!("http://127.0.0.1:{}/dashboard/", port))
+    .send()
+    .await?
+    .error_for_status()?;
+  Ok(start.elapsed())
+}
+
+/*
+ * Probes the NodeCG dashboard until the process exits: Frequently while NodeCG is starting, then
+ * periodically to catch it becoming unresponsive.
+ */
+async fn monitor_health(nodecg: Arc<NodecgInstance>, pid: u32, port: u16) {
+  let client = match reqwest::Client::builder()
+    .timeout(HEALTH_CHECK_TIMEOUT)
+    .build()
+  {
+    Ok(client) => client,
+    Err(_) => return,
+  };
+
+  loop {
+    let interval = match nodecg.status() {
+      Ok(NodecgStatus::Starting) => READINESS_POLL_INTERVAL,
+      Ok(NodecgStatus::Ready) | Ok(NodecgStatus::Unhealthy) => HEALTH_CHECK_INTERVAL,
+      _ => return,
+    };
+    tokio::time::sleep(interval).await;
+    if nodecg.current_pid() != Some(pid) {
+      return;
+    }
+
+    let latency = probe_nodecg(&client, port).await.ok();
+    let _ = nodecg.record_health_check(pid, latency);
   }
+}
 
-  logger.emit_progress("Loading version list...");
-  let client = reqwest::Client::builder().build()?;
+/*
+ * Periodically records the resource usage of the NodeCG process until it exits.
+ */
+async fn sample_metrics(nodecg: Arc<NodecgInstance>, pid: u32) {
+  let mut sys = System::new();
+  let refresh_kind = ProcessRefreshKind::new().with_cpu().with_memory();
+  // CPU usage is calculated from the difference between two refreshes.
+  sys.refresh_processes_specifics(refresh_kind);
+
+  loop {
+    tokio::time::sleep(METRICS_SAMPLE_INTERVAL).await;
+    if nodecg.current_pid() != Some(pid) {
+      return;
+    }
+
+    sys.refresh_processes_specifics(refresh_kind);
+    let process = match sys.process(Pid::from_u32(pid)) {
+      Some(process) => process,
+      None => return,
+    };
+    let parents: Vec<(Pid, Option<Pid>)> = sys
+      .processes()
+      .iter()
+      .map(|(pid, process)| (*pid, process.parent()))
+      .collect();
+
+    let _ = nodecg.record_metrics(NodecgMetrics {
+      pid,
+      timestamp: unix_millis(),
+      cpu_usage: process.cpu_usage(),
+      memory: process.memory(),
+      uptime_secs: process.run_time(),
+      child_process_count: count_descendants(&parents, Pid::from_u32(pid)),
+    });
+  }
+}
+
+/*
+ * Counts the processes started by the given process, including ones started by its children.
+ * Takes pairs of process IDs and their parent's ID.
+ */
+fn count_descendants(parents: &[(Pid, Option<Pid>)], root: Pid) -> usize {
+  let mut count = 0;
+  let mut pending = vec![root];
+  while let Some(parent) = pending.pop() {
+    for (pid, _) in parents.iter().filter(|(_, p)| *p == Some(parent)) {
+      count += 1;
+      pending.push(*pid);
+    }
+  }
+  count
+}
+
+/*
+ * Finds a node process other than the one managed by NCGMGR that is running in the NodeCG install
+ * directory, which is most likely NodeCG started from a terminal.
+ */
+fn find_external_nodecg(sys: &System, install_dir: &str) -> Option<(Pid, String)> {
+  let install_dir = fs::canonicalize(install_dir).ok()?;
+  sys
+    .processes()
+    .iter()
+    .find(|(_, process)| {
+      process.name().to_lowercase().starts_with("node")
+        && process
+          .cwd()
+          .and_then(|cwd| fs::canonicalize(cwd).ok())
+          .map_or(false, |cwd| cwd == install_dir)
+    })
+    .map(|(pid, process)| (*pid, process.name().to_string()))
+}
+
+fn check_port_available(port: u16) -> Result<(), Error> {
+  match TcpListener::bind(("0.0.0.0", port)) {
+    Ok(_) => Ok(()),
+    Err(_) => Err(Error::PortInUse(port)),
+  }
+}
+
+fn parse_nodecg_port(config: &str) -> Option<u16> {
+  serde_json::from_str::<serde_json::Value>(config)
+    .ok()?
+    .get("port")?
+    .as_u64()
+    .and_then(|port| u16::try_from(port).ok())
+}
+
+/*
+ * Reads the port NodeCG listens on from cfg/nodecg.json, falling back to NodeCG's default.
+ */
+pub fn get_nodecg_port(install_dir: &str) -> u16 {
+  fs::read_to_string(Path::new(install_dir).join("cfg").join("nodecg.json"))
+    .ok()
+    .and_then(|config| parse_nodecg_port(&config))
+    .unwrap_or(DEFAULT_NODECG_PORT)
+}
+
+fn restart_delay(consecutive_crashes: u32) -> Duration {
+  let exponent = consecutive_crashes.saturating_sub(1).min(16);
+  (RESTART_BASE_DELAY * 2u32.pow(exponent)).min(RESTART_MAX_DELAY)
+}
+
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const FORCED_EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+#[cfg(target_os = "windows")]
+const CTRL_EVENT_DELIVERY_DELAY: Duration = Duration::from_millis(100);
+
+#[cfg(not(target_os = "windows"))]
+fn request_shutdown(pid: Pid) -> bool {
+  let sys =
+    System::new_with_specifics(RefreshKind::new().with_processes(ProcessRefreshKind::new()));
+  sys
+    .process(pid)
+    .and_then(|process| process.kill_with(sysinfo::Signal::Term))
+    .unwrap_or(false)
+}
+
+// Windows has no SIGTERM. NodeCG is sent Ctrl+C instead, which is only possible from the console
+// it runs in.
+#[cfg(target_os = "windows")]
+fn request_shutdown(pid: Pid) -> bool {
+  use windows_sys::Win32::System::Console::{
+    AttachConsole, FreeConsole, GenerateConsoleCtrlEvent, SetConsoleCtrlHandler, CTRL_C_EVENT,
+  };
+
+  // A process can only be attached to one console at a time.
+  static CONSOLE_LOCK: Mutex<()> = Mutex::new(());
+  let _console = match CONSOLE_LOCK.lock() {
+    Ok(console) => console,
+    Err(_) => return false,
+  };
+
+  unsafe {
+    FreeConsole();
+    if AttachConsole(pid.as_u32()) == 0 {
+      return false;
+    }
+    // Keeps NCGMGR from handling the event itself while it is attached.
+    SetConsoleCtrlHandler(None, 1);
+    let sent = GenerateConsoleCtrlEvent(CTRL_C_EVENT, 0) != 0;
+    FreeConsole();
+    // The event is delivered asynchronously.
+    thread::sleep(CTRL_EVENT_DELIVERY_DELAY);
+    SetConsoleCtrlHandler(None, 0);
+    sent
+  }
+}
+
+/*
+ * Returns true once the process has exited, or false if it is still running after the timeout.
+ */
+fn wait_for_exit(pid: Pid, timeout: Duration) -> bool {
+  let mut sys = System::new();
+  let start = Instant::now();
+  while is_process_alive(&mut sys, pid) {
+    if start.elapsed() >= timeout {
+      return false;
+    }
+    thread::sleep(EXIT_POLL_INTERVAL);
+  }
+
+  true
+}
+
+/*
+ * Every profile gets its own default install directory. The default profile keeps the directory
+ * that was used before profiles were added.
+ */
+pub fn get_default_install_dir(handle: &AppHandle, profile_name: &str) -> Result<String, Error> {
+  let dir_name = config::default_install_dir_name(profile_name);
+  Ok(
+    handle
+      .path()
+      .app_local_data_dir()
+      .map_err(|_| Error::CannotCreateDefaultInstallDir)?
+      .join(dir_name)
+      .to_str()
+      .ok_or(Error::CannotCreateDefaultInstallDir)?
+      .to_string(),
+  )
+}
+
+/*
+ * Looks up the tarball URL for the given NodeCG version, or for the latest version if none is
+ * given. Returns the resolved version number alongside the URL.
+ */
+pub async fn resolve_nodecg_release(
+  client: &reqwest::Client,
+  version: Option<&str>,
+) -> Result<(String, String), Error> {
   let npm_metadata = client
     .get("https://registry.npmjs.org/nodecg/")
     .send()
@@ -115,30 +1170,64 @@ pub async fn install_nodecg(handle: AppHandle, use_default_directory: bool) -> R
     .json::<NPMPackageMetadata>()
     .await?;
 
-  let latest_version = match npm_metadata.dist_tags.get("latest") {
+  let version = match version {
     Some(version) => version,
-    None => {
-      // https://github.com/npm/registry/blob/master/docs/responses/package-metadata.md#full-metadata-format
-      return Err(Error::NodeCGInstall(
-        "Couldn't find latest version of NodeCG (This should never happen!)".to_string(),
-      ));
-    }
+    None => match npm_metadata.dist_tags.get("latest") {
+      Some(version) => version,
+      None => {
+        // https://github.com/npm/registry/blob/master/docs/responses/package-metadata.md#full-metadata-format
+        return Err(Error::NodeCGInstall(
+          "Couldn't find latest version of NodeCG (This should never happen!)".to_string(),
+        ));
+      }
+    },
   };
-  let tarball_url = match npm_metadata.versions.get(latest_version) {
+  let tarball_url = match npm_metadata.versions.get(version) {
     Some(metadata) => metadata.dist.tarball.clone(),
     None => {
       return Err(Error::NodeCGInstall(format!(
-        "Latest NodeCG version ({}) was not found in npm metadata",
-        latest_version
+        "NodeCG version {} was not found in npm metadata",
+        version
       )));
     }
   };
 
-  logger.emit_progress_stepped(1, &format!("Downloading NodeCG {}...", latest_version));
-  let tarball = client.get(tarball_url).send().await?.bytes().await?;
+  Ok((version.to_string(), tarball_url))
+}
 
-  logger.emit_progress_stepped(2, "Extracting archive...");
-  let gz = GzDecoder::new(tarball.iter().as_slice());
+/*
+ * Downloads a NodeCG tarball chunk by chunk, so the download stops soon after the operation is
+ * cancelled instead of running to completion first.
+ */
+pub async fn download_nodecg_tarball(
+  client: &reqwest::Client,
+  tarball_url: &str,
+  logger: &LogEmitter,
+) -> Result<Vec<u8>, Error> {
+  let mut response = client.get(tarball_url).send().await?.error_for_status()?;
+  let mut tarball = Vec::new();
+  loop {
+    logger.check_cancelled()?;
+    // Waiting for a chunk times out regularly, so a stalled download can still be cancelled.
+    let chunk = match tokio::time::timeout(DOWNLOAD_POLL_INTERVAL, response.chunk()).await {
+      Ok(chunk) => chunk?,
+      Err(_) => continue,
+    };
+    match chunk {
+      Some(chunk) => tarball.extend_from_slice(&chunk),
+      None => break,
+    }
+  }
+
+  Ok(tarball)
+}
+
+pub fn extract_nodecg_archive(
+  tarball: &[u8],
+  install_dir: &str,
+  logger: &LogEmitter,
+) -> Result<(), Error> {
+  let gz = GzDecoder::new(tarball);
   let mut archive = Archive::new(gz);
   match archive.entries().map(|entries| {
     entries
@@ -146,7 +1235,7 @@ pub async fn install_nodecg(handle: AppHandle, use_default_directory: bool) -> R
       .map(|mut entry| -> Result<(), Box<dyn std::error::Error + '_>> {
         // Skips the first directory of the archive.
         let entry_path = entry.path()?.components().skip(1).collect::<PathBuf>();
-        let unpack_dir = Path::new(&install_dir).join(entry_path);
+        let unpack_dir = Path::new(install_dir).join(entry_path);
         if entry.header().entry_type() != tar::EntryType::Directory {
           if let Some(p) = unpack_dir.parent() {
             if !p.exists() {
@@ -175,26 +1264,347 @@ pub async fn install_nodecg(handle: AppHandle, use_default_directory: bool) -> R
     Err(e) => return Err(Error::Io(e)),
   }
 
-  logger.emit_progress_stepped(3, "Installing npm dependencies...");
-  let shell = handle.shell();
-  let child = npm::install_npm_dependencies(shell, &install_dir)?;
-  emit_tauri_process_output(&logger, child).await?;
+  Ok(())
+}
+
+/*
+ * Reads the version of NodeCG installed in the given directory from its package.json.
+ */
+pub fn get_installed_nodecg_version(install_dir: &str) -> Result<Option<String>, Error> {
+  let package_path = Path::new(install_dir).join("package.json");
+  if !package_path.exists() {
+    return Ok(None);
+  }
+
+  let package_json: serde_json::Value = serde_json::from_str(&fs::read_to_string(package_path)?)?;
+  Ok(
+    package_json
+      .get("version")
+      .and_then(|v| v.as_str())
+      .map(|v| v.to_string()),
+  )
+}
+
+/*
+ * Returns the directory to install NodeCG into for a profile: either its configured install
+ * directory, or its default one if no other profile uses it already.
+ */
+pub fn get_target_install_dir(
+  handle: &AppHandle,
+  profile: &NodecgProfile,
+  use_default_directory: bool,
+) -> Result<String, Error> {
+  if !use_default_directory {
+    return profile.install_dir.clone().ok_or(Error::MissingInstallDir);
+  }
+  let install_dir = get_default_install_dir(handle, &profile.name)?;
+  let config = config::with_config(handle.clone(), |c| Ok(c))?;
+  config::ensure_install_dir_unused(&config, &profile.name, &install_dir)?;
+  Ok(install_dir)
+}
+
+#[tauri::command]
+pub async fn install_nodecg(handle: AppHandle, use_default_directory: bool) -> Result<(), Error> {
+  let logger = LogEmitter::start_operation(&handle, "install-nodecg", "Install NodeCG", 4);
+  let result = install_profile_nodecg(&handle, use_default_directory, &logger).await;
+  logger.finish_operation(&result);
+  result
+}
+
+/*
+ * Installs the NodeCG version configured in the active profile.
+ */
+async fn install_profile_nodecg(
+  handle: &AppHandle,
+  use_default_directory: bool,
+  logger: &LogEmitter,
+) -> Result<(), Error> {
+  logger.emit_progress_stepped(0, "Installing NodeCG...");
+
+  let profile = config::active_profile(handle.clone())?;
+  let install_dir = get_target_install_dir(handle, &profile, use_default_directory)?;
+  let nodecg_pause = pause_for_bundle_change(
+    handle,
+    LockTarget::Installation(install_dir.clone()),
+    "Install NodeCG",
+  )
+  .await?;
+  logger.emit_log(&format!("NodeCG will be installed in {}", install_dir));
+
+  if use_default_directory {
+    logger.emit_log("Cleaning install directory...");
+    let install_dir_path = Path::new(&install_dir);
+    rm_rf::ensure_removed(install_dir_path)?;
+    fs::create_dir_all(install_dir_path)?;
+    nodecg_pause.lock().restore_lockfile()?;
+    config::update_install_dir(handle.clone(), install_dir.clone())?;
+  }
+
+  let version = profile.nodecg_version.as_deref();
+  if let Err(e) = download_and_install_nodecg(handle, &install_dir, version, logger).await {
+    // Only roll back when the install directory was cleaned by us in the first place.
+    let cancelled = matches!(e, Error::Cancelled);
+    if use_default_directory
+      && (cancelled || config::with_config(handle.clone(), |c| Ok(c.rollback_failed_installs))?)
+    {
+      logger.emit_log("Cleaning install directory...");
+      let install_dir_path = Path::new(&install_dir);
+      rm_rf::ensure_removed(install_dir_path)?;
+      fs::create_dir_all(install_dir_path)?;
+    }
+    return Err(e);
+  }
   logger.emit_progress_stepped(4, "Done!");
   Ok(())
 }
 
+async fn download_and_install_nodecg(
+  handle: &AppHandle,
+  install_dir: &str,
+  version: Option<&str>,
+  logger: &LogEmitter,
+) -> Result<(), Error> {
+  logger.emit_progress("Loading version list...");
+  let client = reqwest::Client::builder().build()?;
+  let (version, tarball_url) = resolve_nodecg_release(&client, version).await?;
+  logger.check_cancelled()?;
+
+  logger.emit_progress_stepped(1, &format!("Downloading NodeCG {}...", version));
+  let tarball = download_nodecg_tarball(&client, &tarball_url, logger).await?;
+
+  logger.emit_progress_stepped(2, "Extracting archive...");
+  extract_nodecg_archive(&tarball, install_dir, logger)?;
+
+  logger.emit_progress_stepped(3, "Installing npm dependencies...");
+  npm::install_dependencies_and_wait(handle.shell(), install_dir, logger).await?;
+  Ok(())
+}
+
+// Commands without a profile operate on the active profile.
+fn resolve_profile(handle: &AppHandle, profile: Option<String>) -> Result<String, Error> {
+  match profile {
+    Some(profile) => Ok(profile),
+    None => config::with_config(handle.clone(), |c| Ok(c.active_profile)),
+  }
+}
+
+fn get_instance(
+  handle: &AppHandle,
+  profile: Option<String>,
+) -> Result<Option<Arc<NodecgInstance>>, Error> {
+  let profile = resolve_profile(handle, profile)?;
+  handle.state::<ManagedNodecg>().get(&profile)
+}
+
 #[tauri::command(async)]
-pub fn start_nodecg(
-  nodecg: tauri::State<'_, ManagedNodecg>,
-) -> Result<String, Error> {
-  nodecg.start()?;
+pub fn start_nodecg(handle: AppHandle, profile: Option<String>) -> Result<String, Error> {
+  let profile = resolve_profile(&handle, profile)?;
+  handle.state::<ManagedNodecg>().start(&profile)?;
   Ok("Started successfully".to_string())
 }
 
 #[tauri::command(async)]
-pub fn stop_nodecg(
-  nodecg: tauri::State<ManagedNodecg>,
-) -> Result<(), Error> {
-  nodecg.stop()?;
+pub fn stop_nodecg(handle: AppHandle, profile: Option<String>) -> Result<(), Error> {
+  let profile = resolve_profile(&handle, profile)?;
+  handle.state::<ManagedNodecg>().stop(&profile)?;
   Ok(())
 }
+
+#[tauri::command]
+pub fn list_nodecg_instances(
+  nodecg: tauri::State<ManagedNodecg>,
+) -> Result<Vec<NodecgInstanceInfo>, Error> {
+  nodecg.list()
+}
+
+#[tauri::command]
+pub fn get_default_nodecg_install_dir(handle: AppHandle) -> Result<String, Error> {
+  let profile = config::with_config(handle.clone(), |c| Ok(c.active_profile))?;
+  get_default_install_dir(&handle, &profile)
+}
+
+#[tauri::command]
+pub fn get_nodecg_status(
+  handle: AppHandle,
+  profile: Option<String>,
+) -> Result<NodecgStatus, Error> {
+  match get_instance(&handle, profile)? {
+    Some(instance) => instance.status(),
+    None => Ok(NodecgStatus::NotRunning),
+  }
+}
+
+#[tauri::command]
+pub fn get_nodecg_health(
+  handle: AppHandle,
+  profile: Option<String>,
+) -> Result<NodecgHealth, Error> {
+  match get_instance(&handle, profile)? {
+    Some(instance) => instance.health(),
+    None => Ok(NodecgHealth::default()),
+  }
+}
+
+#[tauri::command]
+pub fn get_nodecg_metrics_history(
+  handle: AppHandle,
+  profile: Option<String>,
+) -> Result<Vec<NodecgMetrics>, Error> {
+  match get_instance(&handle, profile)? {
+    Some(instance) => instance.metrics_history(),
+    None => Ok(Vec::new()),
+  }
+}
+
+#[tauri::command]
+pub fn get_bundle_issue_counts(
+  handle: AppHandle,
+  profile: Option<String>,
+) -> Result<Vec<BundleIssueCount>, Error> {
+  match get_instance(&handle, profile)? {
+    Some(instance) => instance.bundle_issue_counts(),
+    None => Ok(Vec::new()),
+  }
+}
+
+#[tauri::command]
+pub fn get_nodecg_supervisor_state(
+  handle: AppHandle,
+  profile: Option<String>,
+) -> Result<SupervisorState, Error> {
+  match get_instance(&handle, profile)? {
+    Some(instance) => instance.get_supervisor_state(),
+    None => Ok(SupervisorState::default()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::{Read, Write};
+
+  // Answers a single request with the given status line, e.g. "200 OK"
+  fn start_stand_in_server(status: &'static str) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      let mut buffer = [0; 1024];
+      let _ = stream.read(&mut buffer).unwrap();
+      let response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+      );
+      stream.write_all(response.as_bytes()).unwrap();
+    });
+    port
+  }
+
+  #[test]
+  fn probe_nodecg_responding() {
+    let port = start_stand_in_server("200 OK");
+    let client = reqwest::Client::new();
+    assert!(tauri::async_runtime::block_on(probe_nodecg(&client, port)).is_ok());
+  }
+
+  #[test]
+  fn probe_nodecg_server_error() {
+    let port = start_stand_in_server("500 Internal Server Error");
+    let client = reqwest::Client::new();
+    assert!(tauri::async_runtime::block_on(probe_nodecg(&client, port)).is_err());
+  }
+
+  #[test]
+  fn probe_nodecg_not_listening() {
+    let port = {
+      let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+      listener.local_addr().unwrap().port()
+    };
+    let client = reqwest::Client::new();
+    assert!(tauri::async_runtime::block_on(probe_nodecg(&client, port)).is_err());
+  }
+
+  #[test]
+  fn run_log_key_escapes_profile_names() {
+    assert_eq!("run-nodecg-Default", run_log_key("Default"));
+    assert_eq!("run-nodecg-Main_20_2024", run_log_key("Main 2024"));
+    assert_ne!(run_log_key("a_b"), run_log_key("a b"));
+  }
+
+  #[test]
+  fn restart_delay_doubles() {
+    assert_eq!(Duration::from_secs(1), restart_delay(1));
+    assert_eq!(Duration::from_secs(2), restart_delay(2));
+    assert_eq!(Duration::from_secs(4), restart_delay(3));
+    assert_eq!(Duration::from_secs(8), restart_delay(4));
+  }
+
+  #[test]
+  fn check_port_available_free() {
+    let port = {
+      let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+      listener.local_addr().unwrap().port()
+    };
+    assert!(check_port_available(port).is_ok());
+  }
+
+  #[test]
+  fn check_port_available_in_use() {
+    let listener = TcpListener::bind("0.0.0.0:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    assert_eq!(
+      format!("Port {} is already in use by another application.", port),
+      check_port_available(port).unwrap_err().to_string()
+    );
+  }
+
+  #[test]
+  fn parse_nodecg_port_from_config() {
+    assert_eq!(Some(9091), parse_nodecg_port(r#"{ "port": 9091 }"#));
+  }
+
+  #[test]
+  fn parse_nodecg_port_missing() {
+    assert_eq!(None, parse_nodecg_port(r#"{ "host": "localhost" }"#));
+    assert_eq!(None, parse_nodecg_port(r#"{ "port": 100000 }"#));
+    assert_eq!(None, parse_nodecg_port("not json"));
+  }
+
+  #[test]
+  fn count_descendants_includes_grandchildren() {
+    let pid = Pid::from_u32;
+    let parents = [
+      (pid(1), None),
+      (pid(10), Some(pid(1))),
+      (pid(11), Some(pid(10))),
+      (pid(12), Some(pid(10))),
+      (pid(13), Some(pid(11))),
+      (pid(20), Some(pid(1))),
+    ];
+    assert_eq!(3, count_descendants(&parents, pid(10)));
+    assert_eq!(0, count_descendants(&parents, pid(20)));
+  }
+
+  #[test]
+  fn nodecg_launch_args_order() {
+    let settings = LaunchSettings {
+      node_args: vec!["--max-old-space-size=4096".to_string()],
+      nodecg_args: vec!["--verbose".to_string()],
+      ..Default::default()
+    };
+    assert_eq!(
+      vec![
+        "--max-old-space-size=4096".to_string(),
+        "/nodecg/index.js".to_string(),
+        "--verbose".to_string()
+      ],
+      nodecg_launch_args(&settings, "/nodecg")
+    );
+  }
+
+  #[test]
+  fn restart_delay_is_capped() {
+    assert_eq!(RESTART_MAX_DELAY, restart_delay(10));
+    assert_eq!(RESTART_MAX_DELAY, restart_delay(u32::MAX));
+  }
+}
@@ -1,11 +1,19 @@
 use crate::error::Error;
+use crate::log::{emit_tauri_process_output, err_to_string, LogEmitter, ProcessResult};
 use std::collections::HashMap;
-use std::path::PathBuf;
-use tauri::async_runtime::Receiver;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::async_runtime::{spawn, Receiver};
 use tauri::Wry;
-use tauri_plugin_shell::process::CommandEvent;
+use tauri_plugin_shell::process::{Command, CommandChild, CommandEvent};
 use tauri_plugin_shell::Shell;
 
+// How often a running install checks whether it has been cancelled
+const CANCELLATION_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
 #[derive(serde::Deserialize)]
 pub struct NPMPackageMetadata {
   #[serde(rename = "dist-tags")]
@@ -23,34 +31,418 @@ pub struct NPMPackageVersionDist {
   pub tarball: String,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageManager {
+  Npm,
+  Yarn,
+  Pnpm,
+}
+
+impl PackageManager {
+  /*
+   * Picks the package manager whose lockfile is present in the given directory.
+   * If a directory somehow contains multiple lockfiles, npm wins, then yarn, then pnpm.
+   */
+  pub fn detect<P: AsRef<Path>>(path: P) -> Option<PackageManager> {
+    let path = path.as_ref();
+    if path.join("package-lock.json").exists() || path.join("npm-shrinkwrap.json").exists() {
+      Some(PackageManager::Npm)
+    } else if path.join("yarn.lock").exists() {
+      Some(PackageManager::Yarn)
+    } else if path.join("pnpm-lock.yaml").exists() {
+      Some(PackageManager::Pnpm)
+    } else {
+      None
+    }
+  }
+
+  pub fn program(&self) -> &'static str {
+    match self {
+      PackageManager::Npm => "npm",
+      PackageManager::Yarn => "yarn",
+      PackageManager::Pnpm => "pnpm",
+    }
+  }
+
+  /*
+   * The arguments installing production dependencies in the given directory. With frozen_lockfile
+   * set, the install fails instead of updating an outdated lockfile.
+   */
+  fn install_args(&self, path: &Path, frozen_lockfile: bool) -> Vec<&'static str> {
+    match (self, frozen_lockfile) {
+      (PackageManager::Npm, true) => vec!["ci", "--omit=dev", "--no-progress"],
+      (PackageManager::Npm, false) => vec!["i", "--omit=dev", "--no-progress", "--save=false"],
+      // Yarn 2 and later cannot leave out dev dependencies when installing.
+      (PackageManager::Yarn, true) if is_yarn_berry(path) => vec!["install", "--immutable"],
+      (PackageManager::Yarn, false) if is_yarn_berry(path) => vec!["install", "--no-immutable"],
+      (PackageManager::Yarn, true) => vec![
+        "install",
+        "--frozen-lockfile",
+        "--production",
+        "--non-interactive",
+      ],
+      (PackageManager::Yarn, false) => vec!["install", "--production", "--non-interactive"],
+      (PackageManager::Pnpm, true) => vec!["i", "--frozen-lockfile", "--prod"],
+      (PackageManager::Pnpm, false) => vec!["i", "--no-frozen-lockfile", "--prod"],
+    }
+  }
+}
+
+/*
+ * Yarn 2 and later replaced the install flags of Yarn 1. The version a project uses is read from
+ * the packageManager field of its package.json, or inferred from .yarnrc.yml, which only newer
+ * versions read.
+ */
+fn is_yarn_berry(path: &Path) -> bool {
+  let package_manager = fs::read_to_string(path.join("package.json"))
+    .ok()
+    .and_then(|package_json| serde_json::from_str::<serde_json::Value>(&package_json).ok())
+    .and_then(|package_json| {
+      package_json
+        .get("packageManager")?
+        .as_str()
+        .map(str::to_string)
+    });
+
+  match package_manager {
+    Some(package_manager) => package_manager
+      .strip_prefix("yarn@")
+      .and_then(|version| version.split('.').next())
+      .and_then(|major| major.parse::<u32>().ok())
+      .map_or(false, |major| major >= 2),
+    None => path.join(".yarnrc.yml").exists(),
+  }
+}
+
+impl Display for PackageManager {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.program())
+  }
+}
+
+pub struct DependencyInstall {
+  pub package_manager: PackageManager,
+  pub frozen_lockfile: bool,
+  pub events: Receiver<CommandEvent>,
+  pub child: CommandChild,
+}
+
+impl DependencyInstall {
+  pub fn describe(&self) -> String {
+    if self.frozen_lockfile {
+      format!(
+        "Installing dependencies with {} using lockfile",
+        self.package_manager
+      )
+    } else {
+      format!("Installing dependencies with {}", self.package_manager)
+    }
+  }
+}
+
+#[cfg(target_os = "windows")]
+fn package_manager_command(shell: &Shell<Wry>, program: &str) -> Command {
+  shell.command("cmd").args(["/c", program])
+}
+
+#[cfg(not(target_os = "windows"))]
+fn package_manager_command(shell: &Shell<Wry>, program: &str) -> Command {
+  shell.command(program)
+}
+
+/*
+ * On Windows, the package manager is started through cmd, so killing the child would only stop cmd
+ * and leave the package manager running. taskkill stops the whole process tree instead.
+ */
 #[cfg(target_os = "windows")]
-pub fn install_npm_dependencies(
+fn kill_package_manager(child: CommandChild) {
+  use std::os::windows::process::CommandExt;
+
+  // Keeps taskkill from opening a console window
+  const CREATE_NO_WINDOW: u32 = 0x08000000;
+  let killed = std::process::Command::new("taskkill")
+    .args(["/PID", &child.pid().to_string(), "/T", "/F"])
+    .creation_flags(CREATE_NO_WINDOW)
+    .status()
+    .map_or(false, |status| status.success());
+  if !killed {
+    let _ = child.kill();
+  }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn kill_package_manager(child: CommandChild) {
+  let _ = child.kill();
+}
+
+async fn is_package_manager_available(shell: &Shell<Wry>, package_manager: PackageManager) -> bool {
+  match package_manager_command(shell, package_manager.program())
+    .args(["--version"])
+    .output()
+    .await
+  {
+    Ok(output) => output.status.success(),
+    Err(_) => false,
+  }
+}
+
+/*
+ * Installs production dependencies using the package manager matching the lockfile found in the
+ * given directory. Falls back to a regular npm install if there is no lockfile, or if the matching
+ * package manager is not installed. Unless frozen_lockfile is set, the lockfile may be updated.
+ */
+pub async fn install_dependencies(
   shell: &Shell<Wry>,
   path: &str,
-) -> Result<Receiver<CommandEvent>, Error> {
-  let command = shell
-    .command("cmd")
-    .args(["/c", "npm", "i", "--omit=dev", "--no-progress", "--save=false"])
+  frozen_lockfile: bool,
+) -> Result<DependencyInstall, Error> {
+  let detected = match PackageManager::detect(path) {
+    Some(package_manager) if is_package_manager_available(shell, package_manager).await => {
+      Some(package_manager)
+    }
+    _ => None,
+  };
+
+  let package_manager = detected.unwrap_or(PackageManager::Npm);
+  let frozen_lockfile = frozen_lockfile && detected.is_some();
+  let args = package_manager.install_args(Path::new(path), frozen_lockfile);
+
+  let command = package_manager_command(shell, package_manager.program())
+    .args(args)
     .current_dir(PathBuf::from(path))
     .spawn();
   match command {
-    Ok(cmd) => Ok(cmd.0),
+    Ok(cmd) => Ok(DependencyInstall {
+      package_manager,
+      frozen_lockfile,
+      events: cmd.0,
+      child: cmd.1,
+    }),
     Err(e) => Err(Error::NPMInstall(e.to_string())),
   }
 }
 
-#[cfg(not(target_os = "windows"))]
-pub fn install_npm_dependencies(
+/*
+ * Installs dependencies like install_dependencies, but also waits for the package manager to exit.
+ * Returns an error if the package manager did not exit successfully. If the logger's operation is
+ * cancelled in the meantime, the package manager is killed.
+ * If installing from the lockfile fails, e.g. because it is out of date, the install is retried
+ * once without the lockfile.
+ */
+pub async fn install_dependencies_and_wait(
   shell: &Shell<Wry>,
   path: &str,
-) -> Result<Receiver<CommandEvent>, Error> {
-  let command = shell
-    .command("npm")
-    .args(["i", "--omit=dev", "--no-progress", "--save=false"])
-    .current_dir(PathBuf::from(path))
-    .spawn();
-  match command {
-    Ok(cmd) => Ok(cmd.0),
-    Err(e) => Err(Error::NPMInstall(e.to_string())),
+  logger: &LogEmitter,
+) -> Result<PackageManager, Error> {
+  logger.check_cancelled()?;
+  let install = install_dependencies(shell, path, true).await?;
+  let frozen_lockfile = install.frozen_lockfile;
+  match wait_for_install(install, logger).await {
+    Err(e @ Error::NPMInstall(_)) if frozen_lockfile => {
+      logger.emit_log(&err_to_string("Installing from the lockfile failed", e));
+      logger.emit_log("Retrying without the lockfile...");
+      let install = install_dependencies(shell, path, false).await?;
+      wait_for_install(install, logger).await
+    }
+    result => result,
+  }
+}
+
+async fn wait_for_install(
+  install: DependencyInstall,
+  logger: &LogEmitter,
+) -> Result<PackageManager, Error> {
+  logger.emit_log(&install.describe());
+  let package_manager = install.package_manager;
+  let output = emit_tauri_process_output(logger, install.events);
+
+  let cancellation = logger.cancellation().clone();
+  let child = install.child;
+  let watcher = spawn(async move {
+    while !cancellation.is_cancelled() {
+      tokio::time::sleep(CANCELLATION_POLL_INTERVAL).await;
+    }
+    kill_package_manager(child);
+  });
+  let result = output.await;
+  watcher.abort();
+
+  logger.check_cancelled()?;
+  check_install_result(package_manager, result?)?;
+  Ok(package_manager)
+}
+
+fn check_install_result(
+  package_manager: PackageManager,
+  result: Option<ProcessResult>,
+) -> Result<(), Error> {
+  match result {
+    Some(result) if result.success => Ok(()),
+    Some(result) => {
+      let code = result
+        .code
+        .map_or_else(|| "unknown".to_string(), |code| code.to_string());
+      let mut message = format!("{} exited with code {}", package_manager, code);
+      if !result.stderr_tail.is_empty() {
+        message.push_str(":\n");
+        message.push_str(&result.stderr_tail.join("\n"));
+      }
+      Err(Error::NPMInstall(message))
+    }
+    None => Err(Error::NPMInstall(format!(
+      "{} exited without reporting its status",
+      package_manager
+    ))),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::util::test_util::TestDir;
+
+  fn create_test_dir(name: &str, files: &[&str]) -> TestDir {
+    let dir = TestDir::new(name);
+    for file in files {
+      dir.write(file, "");
+    }
+    dir
+  }
+
+  #[test]
+  fn detect_package_manager_without_lockfile() {
+    let dir = create_test_dir("no-lockfile", &["package.json"]);
+    assert_eq!(None, PackageManager::detect(dir.path()));
+  }
+
+  #[test]
+  fn detect_package_manager_npm() {
+    let dir = create_test_dir("npm", &["package.json", "package-lock.json"]);
+    assert_eq!(
+      Some(PackageManager::Npm),
+      PackageManager::detect(dir.path())
+    );
+  }
+
+  #[test]
+  fn detect_package_manager_yarn() {
+    let dir = create_test_dir("yarn", &["package.json", "yarn.lock"]);
+    assert_eq!(
+      Some(PackageManager::Yarn),
+      PackageManager::detect(dir.path())
+    );
+  }
+
+  #[test]
+  fn detect_package_manager_pnpm() {
+    let dir = create_test_dir("pnpm", &["package.json", "pnpm-lock.yaml"]);
+    assert_eq!(
+      Some(PackageManager::Pnpm),
+      PackageManager::detect(dir.path())
+    );
+  }
+
+  #[test]
+  fn detect_package_manager_prefers_npm() {
+    let dir = create_test_dir("multiple", &["package-lock.json", "yarn.lock"]);
+    assert_eq!(
+      Some(PackageManager::Npm),
+      PackageManager::detect(dir.path())
+    );
+  }
+
+  #[test]
+  fn install_args_yarn_classic() {
+    let dir = create_test_dir("yarn-classic", &["yarn.lock"]);
+    dir.write("package.json", r#"{ "packageManager": "yarn@1.22.19" }"#);
+    assert_eq!(
+      vec![
+        "install",
+        "--frozen-lockfile",
+        "--production",
+        "--non-interactive"
+      ],
+      PackageManager::Yarn.install_args(dir.path(), true)
+    );
+    assert_eq!(
+      vec!["install", "--production", "--non-interactive"],
+      PackageManager::Yarn.install_args(dir.path(), false)
+    );
+  }
+
+  #[test]
+  fn install_args_yarn_berry_from_package_manager() {
+    let dir = create_test_dir("yarn-berry", &["yarn.lock"]);
+    dir.write("package.json", r#"{ "packageManager": "yarn@4.1.0" }"#);
+    assert_eq!(
+      vec!["install", "--immutable"],
+      PackageManager::Yarn.install_args(dir.path(), true)
+    );
+    assert_eq!(
+      vec!["install", "--no-immutable"],
+      PackageManager::Yarn.install_args(dir.path(), false)
+    );
+  }
+
+  #[test]
+  fn install_args_yarn_berry_from_yarnrc() {
+    let dir = create_test_dir("yarn-berry-yarnrc", &["yarn.lock", ".yarnrc.yml"]);
+    dir.write("package.json", "{}");
+    assert_eq!(
+      vec!["install", "--immutable"],
+      PackageManager::Yarn.install_args(dir.path(), true)
+    );
+  }
+
+  #[test]
+  fn install_args_without_frozen_lockfile() {
+    let dir = create_test_dir("unfrozen", &["package.json"]);
+    assert_eq!(
+      vec!["i", "--omit=dev", "--no-progress", "--save=false"],
+      PackageManager::Npm.install_args(dir.path(), false)
+    );
+    assert_eq!(
+      vec!["i", "--no-frozen-lockfile", "--prod"],
+      PackageManager::Pnpm.install_args(dir.path(), false)
+    );
+  }
+
+  #[test]
+  fn check_install_result_success() {
+    let result = ProcessResult {
+      code: Some(0),
+      success: true,
+      stderr_tail: vec!["npm WARN deprecated".to_string()],
+    };
+    assert!(check_install_result(PackageManager::Npm, Some(result)).is_ok());
+  }
+
+  #[test]
+  fn check_install_result_failure() {
+    let result = ProcessResult {
+      code: Some(1),
+      success: false,
+      stderr_tail: vec![
+        "npm ERR! code E404".to_string(),
+        "npm ERR! 404 Not Found".to_string(),
+      ],
+    };
+    assert_eq!(
+      "Error installing npm dependencies: npm exited with code 1:\nnpm ERR! code E404\nnpm ERR! 404 Not Found",
+      check_install_result(PackageManager::Npm, Some(result))
+        .unwrap_err()
+        .to_string()
+    );
+  }
+
+  #[test]
+  fn check_install_result_missing() {
+    assert_eq!(
+      "Error installing npm dependencies: yarn exited without reporting its status",
+      check_install_result(PackageManager::Yarn, None)
+        .unwrap_err()
+        .to_string()
+    );
   }
 }
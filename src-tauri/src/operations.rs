@@ -0,0 +1,585 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{fmt, fs, process};
+
+use sysinfo::{Pid, System};
+use tauri::{AppHandle, Manager};
+
+use crate::config::normalize_install_dir;
+use crate::error::Error;
+use crate::util::{is_process_alive, unix_millis};
+
+// How many finished operations are kept around for list_operations.
+const FINISHED_OPERATIONS_KEPT: usize = 50;
+static LOCKFILE_NAME: &str = "lock";
+// How long a lockfile that does not contain a process ID keeps other instances out.
+const UNREADABLE_LOCKFILE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+pub enum OperationStatus {
+  Running,
+  Succeeded,
+  Failed,
+  Cancelled,
+}
+
+/*
+ * Set when the user cancels an operation. Operations check it between steps and stop with
+ * Error::Cancelled once it is set.
+ */
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+  pub fn cancel(&self) {
+    self.0.store(true, Ordering::Relaxed);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.0.load(Ordering::Relaxed)
+  }
+
+  pub fn check(&self) -> Result<(), Error> {
+    if self.is_cancelled() {
+      Err(Error::Cancelled)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Operation {
+  pub id: u64,
+  // The log key the operation's events are sent under, e.g. install-bundle
+  pub kind: String,
+  pub description: String,
+  pub status: OperationStatus,
+  pub error: Option<String>,
+  // Milliseconds since the Unix epoch
+  pub started_at: u64,
+  pub finished_at: Option<u64>,
+  #[serde(skip)]
+  pub cancellation: CancellationToken,
+}
+
+struct OperationList {
+  next_id: u64,
+  operations: VecDeque<Operation>,
+}
+
+/*
+ * What an operation modifies. Locking an installation conflicts with every other lock in the same
+ * install directory, while bundle locks only conflict with locks on the same bundle.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum LockTarget {
+  Installation(String),
+  Bundle(String, String),
+}
+
+impl LockTarget {
+  fn install_dir(&self) -> &str {
+    match self {
+      LockTarget::Installation(install_dir) | LockTarget::Bundle(install_dir, _) => install_dir,
+    }
+  }
+
+  // Compared instead of install_dir so different spellings of the same directory still conflict.
+  fn install_dir_key(&self) -> String {
+    normalize_install_dir(self.install_dir())
+  }
+
+  fn conflicts_with(&self, other: &LockTarget) -> bool {
+    if self.install_dir_key() != other.install_dir_key() {
+      return false;
+    }
+    match (self, other) {
+      (LockTarget::Bundle(_, bundle), LockTarget::Bundle(_, other_bundle)) => {
+        bundle == other_bundle
+      }
+      _ => true,
+    }
+  }
+}
+
+impl Display for LockTarget {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      LockTarget::Installation(_) => write!(f, "the NodeCG installation"),
+      LockTarget::Bundle(_, bundle) => write!(f, "{}", bundle),
+    }
+  }
+}
+
+struct HeldLock {
+  id: u64,
+  target: LockTarget,
+  description: String,
+}
+
+#[derive(Default)]
+struct LockTable {
+  next_id: u64,
+  held: Vec<HeldLock>,
+}
+
+/*
+ * Keeps an operation's lock until it is dropped. While any lock on an install directory is held,
+ * a lockfile in it keeps other NCGMGR instances from modifying the same installation.
+ */
+pub struct OperationLock {
+  table: Arc<Mutex<LockTable>>,
+  id: u64,
+  install_dir: String,
+}
+
+impl OperationLock {
+  /*
+   * Writes the lockfile again after the operation has emptied the install directory.
+   */
+  pub fn restore_lockfile(&self) -> Result<(), Error> {
+    let path = lockfile_path(&self.install_dir);
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    fs::write(path, process::id().to_string())?;
+    Ok(())
+  }
+
+  /*
+   * Releases the lock before the guard is dropped. Releasing it again has no effect.
+   */
+  pub fn release(&self) {
+    let mut table = match self.table.lock() {
+      Ok(table) => table,
+      Err(_) => return,
+    };
+    let index = match table.held.iter().position(|lock| lock.id == self.id) {
+      Some(index) => index,
+      None => return,
+    };
+    let released = table.held.remove(index);
+    let install_dir = released.target.install_dir();
+    let install_dir_key = released.target.install_dir_key();
+    if !table
+      .held
+      .iter()
+      .any(|lock| lock.target.install_dir_key() == install_dir_key)
+    {
+      if let Err(e) = fs::remove_file(lockfile_path(install_dir)) {
+        if e.kind() != ErrorKind::NotFound {
+          eprintln!("Failed to remove lockfile in {}: {}", install_dir, e);
+        }
+      }
+    }
+  }
+}
+
+impl Drop for OperationLock {
+  fn drop(&mut self) {
+    self.release();
+  }
+}
+
+/*
+ * Keeps track of running long-running commands and the ones that finished recently. Every
+ * operation gets a unique ID, which is included in the events it emits.
+ */
+pub struct OperationRegistry {
+  list: Mutex<OperationList>,
+  locks: Arc<Mutex<LockTable>>,
+}
+
+impl Default for OperationRegistry {
+  fn default() -> Self {
+    OperationRegistry {
+      list: Mutex::new(OperationList {
+        next_id: 1,
+        operations: VecDeque::new(),
+      }),
+      locks: Arc::new(Mutex::new(LockTable::default())),
+    }
+  }
+}
+
+impl OperationRegistry {
+  pub fn start(&self, kind: &str, description: &str) -> Result<Operation, Error> {
+    let mut list = self
+      .list
+      .lock()
+      .map_err(|e| Error::Operation(e.to_string()))?;
+    let operation = Operation {
+      id: list.next_id,
+      kind: kind.to_string(),
+      description: description.to_string(),
+      status: OperationStatus::Running,
+      error: None,
+      started_at: unix_millis(),
+      finished_at: None,
+      cancellation: CancellationToken::default(),
+    };
+    list.next_id += 1;
+    list.operations.push_back(operation.clone());
+    Ok(operation)
+  }
+
+  pub fn finish(&self, id: u64, error: Option<&Error>) -> Result<Option<Operation>, Error> {
+    let mut list = self
+      .list
+      .lock()
+      .map_err(|e| Error::Operation(e.to_string()))?;
+    let operation = match list.operations.iter_mut().find(|o| o.id == id) {
+      Some(operation) => {
+        operation.status = match error {
+          None => OperationStatus::Succeeded,
+          Some(Error::Cancelled) => OperationStatus::Cancelled,
+          Some(_) => OperationStatus::Failed,
+        };
+        operation.error = error.map(|e| e.to_string());
+        operation.finished_at = Some(unix_millis());
+        operation.clone()
+      }
+      None => return Ok(None),
+    };
+
+    let finished_count = list
+      .operations
+      .iter()
+      .filter(|o| o.status != OperationStatus::Running)
+      .count();
+    if finished_count > FINISHED_OPERATIONS_KEPT {
+      if let Some(index) = list
+        .operations
+        .iter()
+        .position(|o| o.status != OperationStatus::Running)
+      {
+        list.operations.remove(index);
+      }
+    }
+    Ok(Some(operation))
+  }
+
+  pub fn cancel(&self, id: u64) -> Result<(), Error> {
+    let list = self
+      .list
+      .lock()
+      .map_err(|e| Error::Operation(e.to_string()))?;
+    match list
+      .operations
+      .iter()
+      .find(|o| o.id == id && o.status == OperationStatus::Running)
+    {
+      Some(operation) => {
+        operation.cancellation.cancel();
+        Ok(())
+      }
+      None => Err(Error::Operation(format!("Operation {} is not running", id))),
+    }
+  }
+
+  pub fn list(&self) -> Result<Vec<Operation>, Error> {
+    self
+      .list
+      .lock()
+      .map(|list| list.operations.iter().cloned().collect())
+      .map_err(|e| Error::Operation(e.to_string()))
+  }
+
+  /*
+   * Locks the target for the given operation, or fails if a conflicting operation is in progress
+   * in this or another NCGMGR instance.
+   */
+  pub fn lock(&self, target: LockTarget, description: &str) -> Result<OperationLock, Error> {
+    let mut table = self
+      .locks
+      .lock()
+      .map_err(|e| Error::Operation(e.to_string()))?;
+    if let Some(conflict) = table
+      .held
+      .iter()
+      .find(|lock| lock.target.conflicts_with(&target))
+    {
+      return Err(Error::OperationConflict(
+        conflict.description.clone(),
+        conflict.target.to_string(),
+      ));
+    }
+
+    let install_dir_key = target.install_dir_key();
+    if !table
+      .held
+      .iter()
+      .any(|lock| lock.target.install_dir_key() == install_dir_key)
+    {
+      acquire_lockfile(target.install_dir())?;
+    }
+
+    table.next_id += 1;
+    let id = table.next_id;
+    let install_dir = target.install_dir().to_string();
+    table.held.push(HeldLock {
+      id,
+      target,
+      description: description.to_string(),
+    });
+    Ok(OperationLock {
+      table: self.locks.clone(),
+      id,
+      install_dir,
+    })
+  }
+
+  /*
+   * Fails if anything in the install directory is being modified, by this or another NCGMGR
+   * instance.
+   */
+  pub fn ensure_unlocked(&self, install_dir: &str) -> Result<(), Error> {
+    let target = LockTarget::Installation(install_dir.to_string());
+    let table = self
+      .locks
+      .lock()
+      .map_err(|e| Error::Operation(e.to_string()))?;
+    if let Some(conflict) = table
+      .held
+      .iter()
+      .find(|lock| lock.target.conflicts_with(&target))
+    {
+      return Err(Error::OperationConflict(
+        conflict.description.clone(),
+        conflict.target.to_string(),
+      ));
+    }
+
+    read_lockfile_owner(target.install_dir()).check()
+  }
+}
+
+fn lockfile_path(install_dir: &str) -> PathBuf {
+  Path::new(install_dir).join(".ncgmgr").join(LOCKFILE_NAME)
+}
+
+enum LockfileOwner {
+  // There is no lockfile, or it was left behind by an instance that is no longer running.
+  Nobody,
+  Instance(u32),
+  // The lockfile could not be read, possibly because its owner is still writing it.
+  Unknown,
+}
+
+impl LockfileOwner {
+  fn check(self) -> Result<(), Error> {
+    match self {
+      LockfileOwner::Nobody => Ok(()),
+      LockfileOwner::Instance(pid) => Err(Error::InstallationLocked(pid)),
+      LockfileOwner::Unknown => Err(Error::InstallationLockedByUnknownInstance),
+    }
+  }
+}
+
+/*
+ * Finds out which other NCGMGR instance holds the lockfile in the install directory.
+ */
+fn read_lockfile_owner(install_dir: &str) -> LockfileOwner {
+  let path = lockfile_path(install_dir);
+  let contents = match fs::read_to_string(&path) {
+    Ok(contents) => contents,
+    Err(e) if e.kind() == ErrorKind::NotFound => return LockfileOwner::Nobody,
+    Err(_) => return unreadable_lockfile_owner(&path),
+  };
+  match contents.trim().parse::<u32>() {
+    Ok(pid) if pid == process::id() => LockfileOwner::Nobody,
+    Ok(pid) if !is_process_alive(&mut System::new(), Pid::from_u32(pid)) => LockfileOwner::Nobody,
+    Ok(pid) => LockfileOwner::Instance(pid),
+    Err(_) => unreadable_lockfile_owner(&path),
+  }
+}
+
+/*
+ * A lockfile without a process ID is created right before its owner writes its ID into it, so it
+ * is only considered stale once it has not been modified for a while.
+ */
+fn unreadable_lockfile_owner(path: &Path) -> LockfileOwner {
+  let age = fs::metadata(path)
+    .and_then(|metadata| metadata.modified())
+    .ok()
+    .and_then(|modified| modified.elapsed().ok());
+  match age {
+    Some(age) if age >= UNREADABLE_LOCKFILE_TIMEOUT => LockfileOwner::Nobody,
+    _ => LockfileOwner::Unknown,
+  }
+}
+
+fn acquire_lockfile(install_dir: &str) -> Result<(), Error> {
+  let path = lockfile_path(install_dir);
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+
+  loop {
+    match OpenOptions::new().write(true).create_new(true).open(&path) {
+      Ok(mut file) => {
+        write!(file, "{}", process::id())?;
+        return Ok(());
+      }
+      Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+        read_lockfile_owner(install_dir).check()?;
+        // The lockfile is stale. Another instance may have removed it in the meantime.
+        if let Err(e) = fs::remove_file(&path) {
+          if e.kind() != ErrorKind::NotFound {
+            return Err(e.into());
+          }
+        }
+      }
+      Err(e) => return Err(e.into()),
+    }
+  }
+}
+
+pub fn lock(
+  handle: &AppHandle,
+  target: LockTarget,
+  description: &str,
+) -> Result<OperationLock, Error> {
+  handle
+    .state::<OperationRegistry>()
+    .lock(target, description)
+}
+
+#[tauri::command]
+pub fn cancel_operation(operations: tauri::State<OperationRegistry>, id: u64) -> Result<(), Error> {
+  operations.cancel(id)
+}
+
+#[tauri::command]
+pub fn list_operations(
+  operations: tauri::State<OperationRegistry>,
+) -> Result<Vec<Operation>, Error> {
+  operations.list()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::util::test_util::TestDir;
+
+  #[test]
+  fn lock_rejects_conflicting_operations() {
+    let registry = OperationRegistry::default();
+    let dir = TestDir::new("lock-conflicts");
+    let install_dir = dir.path_string();
+    let bundle = |name: &str| LockTarget::Bundle(install_dir.clone(), name.to_string());
+
+    let first_lock = registry
+      .lock(bundle("bundle-a"), "Install bundle-a")
+      .unwrap();
+    assert!(lockfile_path(&install_dir).exists());
+    let second_lock = registry
+      .lock(bundle("bundle-b"), "Install bundle-b")
+      .unwrap();
+    assert_eq!(
+      "Another operation (Install bundle-a) is in progress for bundle-a. Please wait for it to finish.",
+      registry
+        .lock(bundle("bundle-a"), "Uninstall bundle-a")
+        .err()
+        .unwrap()
+        .to_string()
+    );
+    assert!(registry
+      .lock(
+        LockTarget::Installation(install_dir.clone()),
+        "Install NodeCG"
+      )
+      .is_err());
+    assert!(registry.ensure_unlocked(&install_dir).is_err());
+
+    drop(first_lock);
+    drop(second_lock);
+    assert!(!lockfile_path(&install_dir).exists());
+    assert!(registry.ensure_unlocked(&install_dir).is_ok());
+  }
+
+  #[test]
+  fn lock_ignores_stale_lockfile() {
+    let registry = OperationRegistry::default();
+    let dir = TestDir::new("stale-lockfile");
+    let install_dir = dir.path_string();
+    fs::create_dir_all(lockfile_path(&install_dir).parent().unwrap()).unwrap();
+    fs::write(lockfile_path(&install_dir), process::id().to_string()).unwrap();
+
+    assert!(registry
+      .lock(LockTarget::Installation(install_dir), "Install NodeCG")
+      .is_ok());
+  }
+
+  #[test]
+  fn lock_respects_lockfile_being_written() {
+    let registry = OperationRegistry::default();
+    let dir = TestDir::new("empty-lockfile");
+    let install_dir = dir.path_string();
+    fs::create_dir_all(lockfile_path(&install_dir).parent().unwrap()).unwrap();
+    fs::write(lockfile_path(&install_dir), "").unwrap();
+
+    assert!(registry
+      .lock(
+        LockTarget::Installation(install_dir.clone()),
+        "Install NodeCG"
+      )
+      .is_err());
+    assert!(registry.ensure_unlocked(&install_dir).is_err());
+    assert!(lockfile_path(&install_dir).exists());
+  }
+
+  #[test]
+  fn finish_sets_status() {
+    let registry = OperationRegistry::default();
+    let first = registry.start("install-bundle", "Installing a").unwrap();
+    let second = registry.start("install-bundle", "Installing b").unwrap();
+    assert_ne!(first.id, second.id);
+
+    let third = registry.start("install-bundle", "Installing c").unwrap();
+    registry.cancel(third.id).unwrap();
+    assert!(third.cancellation.is_cancelled());
+
+    registry.finish(first.id, None).unwrap();
+    registry
+      .finish(second.id, Some(&Error::MissingInstallDir))
+      .unwrap();
+    registry.finish(third.id, Some(&Error::Cancelled)).unwrap();
+    let statuses: Vec<OperationStatus> =
+      registry.list().unwrap().iter().map(|o| o.status).collect();
+    assert_eq!(
+      vec![
+        OperationStatus::Succeeded,
+        OperationStatus::Failed,
+        OperationStatus::Cancelled
+      ],
+      statuses
+    );
+    assert!(registry.cancel(third.id).is_err());
+  }
+
+  #[test]
+  fn finish_drops_oldest_finished_operations() {
+    let registry = OperationRegistry::default();
+    let running = registry
+      .start("install-nodecg", "Installing NodeCG")
+      .unwrap();
+    for _ in 0..=FINISHED_OPERATIONS_KEPT {
+      let operation = registry.start("install-bundle", "Installing a").unwrap();
+      registry.finish(operation.id, None).unwrap();
+    }
+
+    let operations = registry.list().unwrap();
+    assert_eq!(FINISHED_OPERATIONS_KEPT + 1, operations.len());
+    assert_eq!(running.id, operations[0].id);
+    assert_eq!(3, operations[1].id);
+  }
+}
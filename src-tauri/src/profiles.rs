@@ -0,0 +1,93 @@
+use tauri::{AppHandle, Manager};
+
+use crate::config;
+use crate::config::NodecgProfile;
+use crate::error::Error;
+use crate::nodecg::ManagedNodecg;
+use crate::operations::OperationRegistry;
+
+#[tauri::command(async)]
+pub fn create_profile(
+  handle: AppHandle,
+  name: String,
+  install_dir: Option<String>,
+  nodecg_version: Option<String>,
+) -> Result<NodecgProfile, Error> {
+  config::validate_profile_name(&name)?;
+  let mut config = config::with_config(handle.clone(), |c| Ok(c))?;
+  if config.profile(&name).is_some() {
+    return Err(Error::ProfileExists(name));
+  }
+  config::ensure_default_install_dir_unused(&config, &name, None)?;
+
+  let profile = NodecgProfile {
+    install_dir,
+    nodecg_version,
+    ..NodecgProfile::new(&name)
+  };
+  config.profiles.push(profile.clone());
+  config::update_config(handle, config)?;
+  Ok(profile)
+}
+
+#[tauri::command(async)]
+pub fn rename_profile(handle: AppHandle, name: String, new_name: String) -> Result<(), Error> {
+  config::validate_profile_name(&new_name)?;
+  let mut config = config::with_config(handle.clone(), |c| Ok(c))?;
+  if name != new_name && config.profile(&new_name).is_some() {
+    return Err(Error::ProfileExists(new_name));
+  }
+  config::ensure_default_install_dir_unused(&config, &new_name, Some(&name))?;
+  let profile = config
+    .profile_mut(&name)
+    .ok_or_else(|| Error::MissingProfile(name.clone()))?;
+
+  // Fails while NodeCG is running from the profile
+  handle.state::<ManagedNodecg>().remove(&name)?;
+  profile.name = new_name.clone();
+  if config.active_profile == name {
+    config.active_profile = new_name;
+  }
+  config::update_config(handle, config)
+}
+
+/*
+ * Removes a profile from the config. Its installation is left on disk.
+ */
+#[tauri::command(async)]
+pub fn delete_profile(handle: AppHandle, name: String) -> Result<(), Error> {
+  let mut config = config::with_config(handle.clone(), |c| Ok(c))?;
+  let profile = config
+    .profile(&name)
+    .ok_or_else(|| Error::MissingProfile(name.clone()))?;
+  if config.profiles.len() == 1 {
+    return Err(Error::LastProfile);
+  }
+  if let Some(install_dir) = &profile.install_dir {
+    handle
+      .state::<OperationRegistry>()
+      .ensure_unlocked(install_dir)?;
+  }
+
+  // Fails while NodeCG is running from the profile
+  handle.state::<ManagedNodecg>().remove(&name)?;
+  config.profiles.retain(|profile| profile.name != name);
+  if config.active_profile == name {
+    config.active_profile = config.profiles[0].name.clone();
+  }
+  config::update_config(handle, config)
+}
+
+/*
+ * Makes commands operate on the given profile. NodeCG instances of other profiles keep running.
+ */
+#[tauri::command(async)]
+pub fn activate_profile(handle: AppHandle, name: String) -> Result<(), Error> {
+  let mut config = config::with_config(handle.clone(), |c| Ok(c))?;
+  if config.profile(&name).is_none() {
+    return Err(Error::MissingProfile(name));
+  }
+
+  config.active_profile = name;
+  config::update_config(handle, config)
+}
@@ -0,0 +1,364 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use git2::Repository;
+use tauri::AppHandle;
+use tauri_plugin_shell::ShellExt;
+
+use crate::bundles::{resolve_bundle_dir, validate_bundle_name};
+use crate::error::Error;
+use crate::git::{get_head_revision, get_tag_name_at_head};
+use crate::log::LogEmitter;
+use crate::operations::LockTarget;
+use crate::util::{bundle_config_path, unix_millis};
+use crate::{config, git, nodecg, npm};
+
+static SNAPSHOT_METADATA_FILE: &str = "snapshot.json";
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleSnapshot {
+  pub bundle_name: String,
+  pub revision: String,
+  pub tag: Option<String>,
+  pub created_at: u64,
+  pub has_config: bool,
+  pub has_node_modules: bool,
+}
+
+/*
+ * Snapshots are kept inside the NodeCG install directory, so node_modules can be moved there
+ * instead of having to be copied.
+ */
+fn snapshot_dir(install_dir: &str, bundle_name: &str) -> Result<PathBuf, Error> {
+  validate_bundle_name(bundle_name)?;
+  Ok(
+    Path::new(install_dir)
+      .join(".ncgmgr")
+      .join("snapshots")
+      .join(bundle_name),
+  )
+}
+
+/*
+ * Records the bundle's current commit and configuration file, replacing any older snapshot of
+ * the same bundle. If include_node_modules is set, the bundle's node_modules directory is moved
+ * into the snapshot, so the following dependency install starts from a clean slate.
+ */
+pub fn create_snapshot(
+  install_dir: &str,
+  bundle_name: &str,
+  include_node_modules: bool,
+) -> Result<BundleSnapshot, Error> {
+  let bundle_dir = resolve_bundle_dir(install_dir, bundle_name)?;
+  let dir = snapshot_dir(install_dir, bundle_name)?;
+  rm_rf::ensure_removed(&dir)?;
+  fs::create_dir_all(&dir)?;
+
+  let (revision, tag) = {
+    let repo = Repository::open(&bundle_dir)?;
+    (get_head_revision(&repo)?, get_tag_name_at_head(&repo)?)
+  };
+
+  let config_path = bundle_config_path(install_dir, bundle_name);
+  let has_config = config_path.exists();
+  if has_config {
+    fs::copy(&config_path, dir.join("config.json"))?;
+  }
+
+  let node_modules_path = bundle_dir.join("node_modules");
+  let has_node_modules = include_node_modules && node_modules_path.exists();
+  if has_node_modules {
+    fs::rename(&node_modules_path, dir.join("node_modules"))?;
+  }
+
+  let snapshot = BundleSnapshot {
+    bundle_name: bundle_name.to_string(),
+    revision,
+    tag,
+    created_at: unix_millis(),
+    has_config,
+    has_node_modules,
+  };
+  fs::write(
+    dir.join(SNAPSHOT_METADATA_FILE),
+    serde_json::to_string_pretty(&snapshot)?,
+  )?;
+
+  Ok(snapshot)
+}
+
+pub fn read_snapshot(
+  install_dir: &str,
+  bundle_name: &str,
+) -> Result<Option<BundleSnapshot>, Error> {
+  let metadata_path = snapshot_dir(install_dir, bundle_name)?.join(SNAPSHOT_METADATA_FILE);
+  if !metadata_path.exists() {
+    return Ok(None);
+  }
+
+  let metadata = fs::read_to_string(metadata_path)?;
+  Ok(Some(serde_json::from_str(&metadata)?))
+}
+
+/*
+ * Moves the node_modules directory kept in a bundle's snapshot back into the bundle, replacing
+ * whatever a failed install left behind. Returns whether there was anything to restore.
+ */
+pub fn restore_node_modules(install_dir: &str, bundle_name: &str) -> Result<bool, Error> {
+  let mut snapshot = match read_snapshot(install_dir, bundle_name)? {
+    Some(snapshot) if snapshot.has_node_modules => snapshot,
+    _ => return Ok(false),
+  };
+  let dir = snapshot_dir(install_dir, bundle_name)?;
+  let node_modules_path = resolve_bundle_dir(install_dir, bundle_name)?.join("node_modules");
+  rm_rf::ensure_removed(&node_modules_path)?;
+  fs::rename(dir.join("node_modules"), node_modules_path)?;
+
+  // The snapshot stays usable for a later restore, which then reinstalls the dependencies.
+  snapshot.has_node_modules = false;
+  fs::write(
+    dir.join(SNAPSHOT_METADATA_FILE),
+    serde_json::to_string_pretty(&snapshot)?,
+  )?;
+  Ok(true)
+}
+
+/*
+ * Checks out the snapshot's revision and puts back its configuration file and node_modules.
+ * Returns whether node_modules was restored, as the dependencies need to be reinstalled otherwise.
+ */
+fn restore_snapshot_files(
+  install_dir: &str,
+  bundle_name: &str,
+  snapshot: &BundleSnapshot,
+  log: &dyn Fn(&str),
+) -> Result<bool, Error> {
+  let dir = snapshot_dir(install_dir, bundle_name)?;
+  let bundle_dir = resolve_bundle_dir(install_dir, bundle_name)?;
+
+  log(&format!(
+    "Checking out {}...",
+    snapshot.tag.as_ref().unwrap_or(&snapshot.revision)
+  ));
+  {
+    let repo = Repository::open(&bundle_dir)?;
+    git::checkout_version(&repo, snapshot.revision.clone())?;
+  }
+
+  let config_path = bundle_config_path(install_dir, bundle_name);
+  if snapshot.has_config {
+    log("Restoring bundle configuration...");
+    if let Some(parent) = config_path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    fs::copy(dir.join("config.json"), &config_path)?;
+  } else if config_path.exists() {
+    log("Removing bundle configuration created after the snapshot...");
+    fs::remove_file(&config_path)?;
+  }
+
+  if snapshot.has_node_modules {
+    log("Restoring npm dependencies...");
+  }
+  restore_node_modules(install_dir, bundle_name)
+}
+
+/*
+ * Returns the bundle to the state recorded in its snapshot. The snapshot is removed afterwards.
+ * If node_modules was not included in the snapshot, dependencies are reinstalled instead.
+ */
+pub async fn restore_snapshot(
+  handle: &AppHandle,
+  install_dir: &str,
+  bundle_name: &str,
+  logger: &LogEmitter,
+) -> Result<BundleSnapshot, Error> {
+  let snapshot = read_snapshot(install_dir, bundle_name)?
+    .ok_or_else(|| Error::MissingSnapshot(bundle_name.to_string()))?;
+
+  let restored_node_modules =
+    restore_snapshot_files(install_dir, bundle_name, &snapshot, &|message| {
+      logger.emit_log(message)
+    })?;
+  if !restored_node_modules {
+    let bundle_dir = resolve_bundle_dir(install_dir, bundle_name)?;
+    npm::install_dependencies_and_wait(handle.shell(), &bundle_dir.to_string_lossy(), logger)
+      .await?;
+  }
+
+  rm_rf::ensure_removed(snapshot_dir(install_dir, bundle_name)?)?;
+  Ok(snapshot)
+}
+
+#[tauri::command(async)]
+pub fn get_bundle_snapshot(
+  handle: AppHandle,
+  bundle_name: String,
+) -> Result<Option<BundleSnapshot>, Error> {
+  let install_dir = config::with_config(handle.clone(), |c| Ok(c.install_dir()))?
+    .ok_or(Error::MissingInstallDir)?;
+
+  read_snapshot(&install_dir, &bundle_name)
+}
+
+#[tauri::command(async)]
+pub async fn restore_bundle_snapshot(handle: AppHandle, bundle_name: String) -> Result<(), Error> {
+  let logger = LogEmitter::start_operation(
+    &handle,
+    "restore-bundle-snapshot",
+    &format!("Restore {}", bundle_name),
+    1,
+  );
+  let result = restore_bundle_from_snapshot(&handle, &bundle_name, &logger).await;
+  logger.finish_operation(&result);
+  result
+}
+
+async fn restore_bundle_from_snapshot(
+  handle: &AppHandle,
+  bundle_name: &str,
+  logger: &LogEmitter,
+) -> Result<(), Error> {
+  logger.emit_progress_stepped(0, &format!("Restoring {}...", bundle_name));
+  let install_dir = config::with_config(handle.clone(), |c| Ok(c.install_dir()))?
+    .ok_or(Error::MissingInstallDir)?;
+  if !resolve_bundle_dir(&install_dir, bundle_name)?.exists() {
+    return Err(Error::MissingBundle(bundle_name.to_string()));
+  }
+
+  let _nodecg_pause = nodecg::pause_for_bundle_change(
+    handle,
+    LockTarget::Bundle(install_dir.clone(), bundle_name.to_string()),
+    &format!("Restore {}", bundle_name),
+  )
+  .await?;
+  restore_snapshot(handle, &install_dir, bundle_name, logger).await?;
+  logger.emit_progress_stepped(1, "Done!");
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::util::test_util::TestDir;
+  use git2::build::CheckoutBuilder;
+  use git2::{Oid, Signature};
+
+  // Commits a tree containing only package.json, without touching the working directory.
+  fn commit_package_json(repo: &Repository, contents: &str, update_ref: Option<&str>) -> Oid {
+    let blob = repo.blob(contents.as_bytes()).unwrap();
+    let mut builder = repo.treebuilder(None).unwrap();
+    builder.insert("package.json", blob, 0o100644).unwrap();
+    let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+    let signature = Signature::now("NCGMGR", "ncgmgr@example.com").unwrap();
+    let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    repo
+      .commit(
+        update_ref, &signature, &signature, contents, &tree, &parents,
+      )
+      .unwrap()
+  }
+
+  // Sets up an installed bundle at its first commit, with a configuration file and node_modules.
+  fn install_test_bundle(dir: &TestDir) -> Repository {
+    let repo = Repository::init(dir.path().join("bundles/test-bundle")).unwrap();
+    commit_package_json(&repo, "{\"version\": \"1.0.0\"}", Some("HEAD"));
+    repo
+      .checkout_head(Some(CheckoutBuilder::new().force()))
+      .unwrap();
+    dir.write("cfg/test-bundle.json", "{\"setting\": 1}");
+    dir.write("bundles/test-bundle/node_modules/dep/index.js", "1.0.0");
+    repo
+  }
+
+  fn read(dir: &TestDir, relative_path: &str) -> String {
+    fs::read_to_string(dir.path().join(relative_path)).unwrap()
+  }
+
+  #[test]
+  fn snapshot_round_trip() {
+    let dir = TestDir::new("snapshot-round-trip");
+    let install_dir = dir.path_string();
+    let repo = install_test_bundle(&dir);
+
+    let snapshot = create_snapshot(&install_dir, "test-bundle", true).unwrap();
+    assert!(snapshot.has_config);
+    assert!(snapshot.has_node_modules);
+    assert!(!dir.path().join("bundles/test-bundle/node_modules").exists());
+
+    let commit = commit_package_json(&repo, "{\"version\": \"2.0.0\"}", None);
+    repo
+      .tag_lightweight("2.0.0", &repo.find_object(commit, None).unwrap(), false)
+      .unwrap();
+    git::checkout_version(&repo, "2.0.0".to_string()).unwrap();
+    dir.write("cfg/test-bundle.json", "{\"setting\": 2}");
+    dir.write("bundles/test-bundle/node_modules/dep/index.js", "2.0.0");
+
+    let snapshot = read_snapshot(&install_dir, "test-bundle").unwrap().unwrap();
+    assert!(restore_snapshot_files(&install_dir, "test-bundle", &snapshot, &|_| {}).unwrap());
+
+    assert_eq!(
+      "{\"version\": \"1.0.0\"}",
+      read(&dir, "bundles/test-bundle/package.json")
+    );
+    assert_eq!("{\"setting\": 1}", read(&dir, "cfg/test-bundle.json"));
+    assert_eq!(
+      "1.0.0",
+      read(&dir, "bundles/test-bundle/node_modules/dep/index.js")
+    );
+  }
+
+  #[test]
+  fn snapshot_round_trip_removes_new_config() {
+    let dir = TestDir::new("snapshot-new-config");
+    let install_dir = dir.path_string();
+    install_test_bundle(&dir);
+    fs::remove_file(dir.path().join("cfg/test-bundle.json")).unwrap();
+
+    let snapshot = create_snapshot(&install_dir, "test-bundle", false).unwrap();
+    assert!(!snapshot.has_config);
+    assert!(!snapshot.has_node_modules);
+    dir.write("cfg/test-bundle.json", "{\"setting\": 2}");
+
+    assert!(!restore_snapshot_files(&install_dir, "test-bundle", &snapshot, &|_| {}).unwrap());
+    assert!(!dir.path().join("cfg/test-bundle.json").exists());
+    assert_eq!(
+      "1.0.0",
+      read(&dir, "bundles/test-bundle/node_modules/dep/index.js")
+    );
+  }
+
+  #[test]
+  fn snapshot_rejects_invalid_bundle_names() {
+    let dir = TestDir::new("snapshot-invalid-name");
+    let install_dir = dir.path_string();
+    assert!(create_snapshot(&install_dir, "..", false).is_err());
+    assert!(read_snapshot(&install_dir, "../../x").is_err());
+  }
+
+  #[test]
+  fn restore_node_modules_after_failed_install() {
+    let dir = TestDir::new("snapshot-node-modules");
+    let install_dir = dir.path_string();
+    install_test_bundle(&dir);
+
+    create_snapshot(&install_dir, "test-bundle", true).unwrap();
+    dir.write("bundles/test-bundle/node_modules/dep/partial.js", "");
+
+    assert!(restore_node_modules(&install_dir, "test-bundle").unwrap());
+    assert_eq!(
+      "1.0.0",
+      read(&dir, "bundles/test-bundle/node_modules/dep/index.js")
+    );
+    assert!(!dir
+      .path()
+      .join("bundles/test-bundle/node_modules/dep/partial.js")
+      .exists());
+
+    let snapshot = read_snapshot(&install_dir, "test-bundle").unwrap().unwrap();
+    assert!(!snapshot.has_node_modules);
+    assert!(!restore_node_modules(&install_dir, "test-bundle").unwrap());
+  }
+}
@@ -0,0 +1,228 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tauri::AppHandle;
+
+use crate::bundles::resolve_bundle_dir;
+use crate::error::Error;
+use crate::operations::LockTarget;
+use crate::util::{bundle_config_path, unix_millis};
+use crate::{config, operations};
+
+static TRASH_METADATA_FILE: &str = "trashed.json";
+static TRASHED_BUNDLE_DIR: &str = "bundle";
+static TRASHED_CONFIG_FILE: &str = "config.json";
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashedBundle {
+  pub id: String,
+  pub bundle_name: String,
+  // Milliseconds since the Unix epoch
+  pub deleted_at: u64,
+  // The bundle is deleted permanently after this time, or never if it is not set.
+  pub expires_at: Option<u64>,
+  pub has_config: bool,
+}
+
+/*
+ * Like snapshots, the trash is kept inside the NodeCG install directory, so bundles can be moved
+ * there instead of having to be copied.
+ */
+fn trash_dir(install_dir: &str) -> PathBuf {
+  Path::new(install_dir).join(".ncgmgr").join("trash")
+}
+
+/*
+ * Moves a bundle and its configuration file to the trash, where it is kept for retention_days, or
+ * forever if retention_days is 0.
+ */
+pub fn move_to_trash(
+  install_dir: &str,
+  bundle_name: &str,
+  retention_days: u32,
+) -> Result<TrashedBundle, Error> {
+  let bundle_dir = resolve_bundle_dir(install_dir, bundle_name)?;
+  if !bundle_dir.exists() {
+    return Err(Error::MissingBundle(bundle_name.to_string()));
+  }
+
+  let deleted_at = unix_millis();
+  let id = format!("{}-{}", deleted_at, bundle_name);
+  let dir = trash_dir(install_dir).join(&id);
+  fs::create_dir_all(&dir)?;
+
+  fs::rename(&bundle_dir, dir.join(TRASHED_BUNDLE_DIR))?;
+  let config_path = bundle_config_path(install_dir, bundle_name);
+  let has_config = config_path.exists();
+  if has_config {
+    fs::rename(&config_path, dir.join(TRASHED_CONFIG_FILE))?;
+  }
+
+  let trashed_bundle = TrashedBundle {
+    id,
+    bundle_name: bundle_name.to_string(),
+    deleted_at,
+    expires_at: match retention_days {
+      0 => None,
+      days => Some(deleted_at + days as u64 * 24 * 60 * 60 * 1000),
+    },
+    has_config,
+  };
+  fs::write(
+    dir.join(TRASH_METADATA_FILE),
+    serde_json::to_string_pretty(&trashed_bundle)?,
+  )?;
+  Ok(trashed_bundle)
+}
+
+pub fn list_trash(install_dir: &str) -> Result<Vec<TrashedBundle>, Error> {
+  let dir = trash_dir(install_dir);
+  let mut trashed_bundles = Vec::new();
+  if !dir.exists() {
+    return Ok(trashed_bundles);
+  }
+
+  for entry in fs::read_dir(dir)? {
+    let metadata_path = entry?.path().join(TRASH_METADATA_FILE);
+    if metadata_path.exists() {
+      trashed_bundles.push(serde_json::from_str(&fs::read_to_string(metadata_path)?)?);
+    }
+  }
+  trashed_bundles.sort_by(|a: &TrashedBundle, b| b.deleted_at.cmp(&a.deleted_at));
+  Ok(trashed_bundles)
+}
+
+pub fn remove_expired(install_dir: &str) -> Result<(), Error> {
+  let now = unix_millis();
+  for trashed_bundle in list_trash(install_dir)? {
+    if trashed_bundle
+      .expires_at
+      .map_or(false, |expires_at| expires_at <= now)
+    {
+      rm_rf::ensure_removed(trash_dir(install_dir).join(&trashed_bundle.id))?;
+    }
+  }
+  Ok(())
+}
+
+pub fn restore_from_trash(install_dir: &str, id: &str) -> Result<TrashedBundle, Error> {
+  let trashed_bundle = list_trash(install_dir)?
+    .into_iter()
+    .find(|trashed_bundle| trashed_bundle.id == id)
+    .ok_or_else(|| Error::MissingTrashedBundle(id.to_string()))?;
+  let bundle_name = &trashed_bundle.bundle_name;
+  let bundle_dir = resolve_bundle_dir(install_dir, bundle_name)?;
+  if bundle_dir.exists() {
+    return Err(Error::BundleRestore(
+      bundle_name.clone(),
+      "A bundle with the same name is installed.".to_string(),
+    ));
+  }
+
+  let dir = trash_dir(install_dir).join(&trashed_bundle.id);
+  let config_path = bundle_config_path(install_dir, bundle_name);
+  if trashed_bundle.has_config && config_path.exists() {
+    return Err(Error::BundleRestore(
+      bundle_name.clone(),
+      "The bundle has a new configuration file.".to_string(),
+    ));
+  }
+
+  fs::create_dir_all(Path::new(install_dir).join("bundles"))?;
+  fs::rename(dir.join(TRASHED_BUNDLE_DIR), &bundle_dir)?;
+  if trashed_bundle.has_config {
+    if let Some(parent) = config_path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    fs::rename(dir.join(TRASHED_CONFIG_FILE), &config_path)?;
+  }
+  rm_rf::ensure_removed(&dir)?;
+  Ok(trashed_bundle)
+}
+
+#[tauri::command(async)]
+pub fn list_trashed_bundles(handle: AppHandle) -> Result<Vec<TrashedBundle>, Error> {
+  let install_dir = config::with_config(handle.clone(), |c| Ok(c.install_dir()))?
+    .ok_or(Error::MissingInstallDir)?;
+  remove_expired(&install_dir)?;
+  list_trash(&install_dir)
+}
+
+#[tauri::command(async)]
+pub fn restore_trashed_bundle(handle: AppHandle, id: String) -> Result<TrashedBundle, Error> {
+  let install_dir = config::with_config(handle.clone(), |c| Ok(c.install_dir()))?
+    .ok_or(Error::MissingInstallDir)?;
+  let trashed_bundle = list_trash(&install_dir)?
+    .into_iter()
+    .find(|trashed_bundle| trashed_bundle.id == id)
+    .ok_or_else(|| Error::MissingTrashedBundle(id.clone()))?;
+  let _lock = operations::lock(
+    &handle,
+    LockTarget::Bundle(install_dir.clone(), trashed_bundle.bundle_name.clone()),
+    &format!("Restore {}", trashed_bundle.bundle_name),
+  )?;
+
+  restore_from_trash(&install_dir, &id)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::util::test_util::TestDir;
+
+  fn create_test_install_dir(name: &str) -> TestDir {
+    let dir = TestDir::new(name);
+    dir.write("bundles/test-bundle/package.json", "{}");
+    dir.write("cfg/test-bundle.json", "{}");
+    dir
+  }
+
+  #[test]
+  fn move_to_trash_and_restore() {
+    let dir = create_test_install_dir("trash-restore");
+    let install_dir = dir.path_string();
+    let bundle_dir = Path::new(&install_dir).join("bundles").join("test-bundle");
+    let config_path = bundle_config_path(&install_dir, "test-bundle");
+
+    let trashed_bundle = move_to_trash(&install_dir, "test-bundle", 7).unwrap();
+    assert!(trashed_bundle.has_config);
+    assert!(!bundle_dir.exists());
+    assert!(!config_path.exists());
+    assert_eq!(1, list_trash(&install_dir).unwrap().len());
+
+    restore_from_trash(&install_dir, &trashed_bundle.id).unwrap();
+    assert!(bundle_dir.join("package.json").exists());
+    assert!(config_path.exists());
+    assert!(list_trash(&install_dir).unwrap().is_empty());
+  }
+
+  #[test]
+  fn remove_expired_deletes_old_bundles() {
+    let dir = create_test_install_dir("trash-expired");
+    let install_dir = dir.path_string();
+    let mut trashed_bundle = move_to_trash(&install_dir, "test-bundle", 7).unwrap();
+    trashed_bundle.expires_at = Some(trashed_bundle.deleted_at);
+    fs::write(
+      trash_dir(&install_dir)
+        .join(&trashed_bundle.id)
+        .join(TRASH_METADATA_FILE),
+      serde_json::to_string_pretty(&trashed_bundle).unwrap(),
+    )
+    .unwrap();
+
+    remove_expired(&install_dir).unwrap();
+    assert!(list_trash(&install_dir).unwrap().is_empty());
+  }
+
+  #[test]
+  fn remove_expired_keeps_bundles_without_retention_limit() {
+    let dir = create_test_install_dir("trash-no-limit");
+    let install_dir = dir.path_string();
+    let trashed_bundle = move_to_trash(&install_dir, "test-bundle", 0).unwrap();
+    assert_eq!(None, trashed_bundle.expires_at);
+
+    remove_expired(&install_dir).unwrap();
+    assert_eq!(1, list_trash(&install_dir).unwrap().len());
+  }
+}
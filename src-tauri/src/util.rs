@@ -0,0 +1,83 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sysinfo::{Pid, ProcessStatus, System};
+
+/*
+ * Milliseconds since the Unix epoch, which is how timestamps are sent to the frontend.
+ */
+pub fn unix_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |d| d.as_millis() as u64)
+}
+
+pub fn bundle_config_path(install_dir: &str, bundle_name: &str) -> PathBuf {
+  Path::new(install_dir)
+    .join("cfg")
+    .join(format!("{}.json", bundle_name))
+}
+
+/*
+ * Zombie processes have exited but were not reaped yet, so they do not count as alive.
+ */
+pub fn is_process_alive(sys: &mut System, pid: Pid) -> bool {
+  sys.refresh_process(pid)
+    && sys
+      .process(pid)
+      .map_or(false, |process| process.status() != ProcessStatus::Zombie)
+}
+
+#[cfg(test)]
+pub mod test_util {
+  use std::fs;
+  use std::path::{Path, PathBuf};
+  use std::process;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  static NEXT_TEST_DIR_ID: AtomicUsize = AtomicUsize::new(0);
+
+  /*
+   * A temporary directory with a name unique to the test run, which is removed again once dropped.
+   */
+  pub struct TestDir {
+    path: PathBuf,
+  }
+
+  impl TestDir {
+    pub fn new(name: &str) -> Self {
+      let path = std::env::temp_dir().join(format!(
+        "ncgmgr-test-{}-{}-{}",
+        name,
+        process::id(),
+        NEXT_TEST_DIR_ID.fetch_add(1, Ordering::Relaxed)
+      ));
+      rm_rf::ensure_removed(&path).unwrap();
+      fs::create_dir_all(&path).unwrap();
+      TestDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+      &self.path
+    }
+
+    pub fn path_string(&self) -> String {
+      self.path.to_string_lossy().to_string()
+    }
+
+    // Creates a file relative to the directory, along with its parent directories.
+    pub fn write(&self, relative_path: &str, contents: &str) {
+      let path = self.path.join(relative_path);
+      if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+      }
+      fs::write(path, contents).unwrap();
+    }
+  }
+
+  impl Drop for TestDir {
+    fn drop(&mut self) {
+      let _ = rm_rf::ensure_removed(&self.path);
+    }
+  }
+}
@@ -7,6 +7,7 @@ import { useConfigStore } from '@/store/configStore'
 import { useDependencyStore } from '@/store/dependencyStore'
 import { useNodecgStore } from '@/store/nodecgStore'
 import { useLogStore } from '@/store/logStore'
+import { runLogKey } from '@/service/nodecgService'
 
 function setColorTheme(useLightMode: boolean) {
     if (useLightMode) {
@@ -34,7 +35,8 @@ function setColorTheme(useLightMode: boolean) {
     await nodecgStore.checkNodecgStatus()
     await nodecgStore.listenForRunStatus()
     const logStore = useLogStore()
-    await logStore.listen('run-nodecg')
+    await logStore.listenForOperations()
+    await logStore.listen(runLogKey(configStore.userConfig.activeProfile))
 
     setUpErrorHandler(app)
 
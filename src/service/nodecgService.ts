@@ -1,15 +1,27 @@
-import { mkdir, readDir, readTextFile, remove, writeFile } from '@tauri-apps/plugin-fs'
+import { mkdir, readDir, readTextFile, writeFile } from '@tauri-apps/plugin-fs'
 import { PackageSchema } from '@/types/package'
 import isEmpty from 'lodash/isEmpty'
 import { InstallStatus } from '@/store/nodecgStore'
 import { invoke } from '@tauri-apps/api/core'
 import { fileExists, folderExists } from '@/util/fs'
 import { open } from '@tauri-apps/plugin-shell'
-import { NodecgConfiguration } from '@/types/nodecg'
-import { appLocalDataDir } from '@tauri-apps/api/path'
+import { NodecgConfiguration, TrashedBundle } from '@/types/nodecg'
+
+// Must match run_log_key in nodecg.rs
+export function runLogKey (profile: string): string {
+    let key = 'run-nodecg-'
+    for (const char of profile) {
+        if (/^[a-zA-Z0-9-]$/.test(char)) {
+            key += char
+        } else {
+            key += `_${(char.codePointAt(0) ?? 0).toString(16)}_`
+        }
+    }
+    return key
+}
 
 export async function getDefaultInstallDir (): Promise<string> {
-    return `${await appLocalDataDir()}/nodecg`
+    return invoke<string>('get_default_nodecg_install_dir')
 }
 
 export async function getNodecgStatus (directory: string | null): Promise<{ status: InstallStatus, message: string }> {
@@ -92,15 +104,20 @@ export async function configFileExists (bundleName: string, nodecgPath: string |
     return fileExists(`${nodecgPath}/cfg/${bundleName}.json`)
 }
 
-export async function removeBundle (bundleName: string, nodecgPath: string | null): Promise<[string, void]> {
-    return Promise.all([
-        invoke<string>('uninstall_bundle', { bundleName }),
-        (async () => {
-            if (await configFileExists(bundleName, nodecgPath)) {
-                return remove(`${nodecgPath}/cfg/${bundleName}.json`)
-            }
-        })()
-    ])
+export async function requestBundleRemoval (bundleName: string): Promise<string> {
+    return invoke<string>('request_bundle_uninstall', { bundleName })
+}
+
+export async function removeBundle (bundleName: string, confirmationToken: string): Promise<TrashedBundle> {
+    return invoke<TrashedBundle>('uninstall_bundle', { bundleName, confirmationToken })
+}
+
+export async function listTrashedBundles (): Promise<TrashedBundle[]> {
+    return invoke<TrashedBundle[]>('list_trashed_bundles')
+}
+
+export async function restoreTrashedBundle (id: string): Promise<TrashedBundle> {
+    return invoke<TrashedBundle>('restore_trashed_bundle', { id })
 }
 
 export async function openConfigFile (bundleName: string, nodecgPath: string | null): Promise<void> {
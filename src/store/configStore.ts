@@ -1,29 +1,83 @@
 import { defineStore } from 'pinia'
 import { appConfigDir } from '@tauri-apps/api/path'
 import { Store } from '@tauri-apps/plugin-store'
-import { readonly, ref } from 'vue'
+import { computed, readonly, ref } from 'vue'
 import { invoke } from '@tauri-apps/api/core'
 import { type } from '@tauri-apps/plugin-os'
 
 const STORE_CONFIG_KEY = 'config'
 
+export interface LaunchSettings {
+    nodePath: string | null
+    nodeArgs: string[]
+    nodecgArgs: string[]
+    env: Record<string, string>
+}
+
+export interface NodecgProfile {
+    name: string
+    installDir: string | null
+    nodecgVersion: string | null
+    launchSettings: LaunchSettings
+}
+
 export interface Configuration {
-    nodecgInstallDir: string | null
+    version: number
+    profiles: NodecgProfile[]
+    activeProfile: string
     enableErrorLog: boolean
+    rollbackFailedInstalls: boolean
+    snapshotNodeModules: boolean
+    shutdownGracePeriodSecs: number
+    autoRestart: boolean
+    maxRestartAttempts: number
+    logRetentionDays: number
+    bundleChangePolicy: 'Refuse' | 'StopAndRestart'
+    trashRetentionDays: number
+}
+
+export interface ConfigReset {
+    reason: string
+    backupPath: string
 }
 
 export const useConfigStore = defineStore('config', () => {
     const userConfig = ref<Configuration>({
-        nodecgInstallDir: null,
-        enableErrorLog: false
+        version: 2,
+        profiles: [{
+            name: 'Default',
+            installDir: null,
+            nodecgVersion: null,
+            launchSettings: {
+                nodePath: null,
+                nodeArgs: [],
+                nodecgArgs: [],
+                env: {}
+            }
+        }],
+        activeProfile: 'Default',
+        enableErrorLog: false,
+        rollbackFailedInstalls: true,
+        snapshotNodeModules: false,
+        shutdownGracePeriodSecs: 10,
+        autoRestart: true,
+        maxRestartAttempts: 5,
+        logRetentionDays: 14,
+        bundleChangePolicy: 'Refuse',
+        trashRetentionDays: 7
     })
     const allowOpenInTerminal = ref(false)
+    const configReset = ref<ConfigReset | null>(null)
+    const activeProfile = computed(() =>
+        userConfig.value.profiles.find(profile => profile.name === userConfig.value.activeProfile) ?? null)
+    const installDir = computed(() => activeProfile.value?.installDir ?? null)
     let store: Store
 
     async function init() {
         const configDir = await appConfigDir()
         store = new Store(`${configDir}/config.json`)
         const config = await store.get<Configuration>(STORE_CONFIG_KEY)
+        configReset.value = await invoke<ConfigReset | null>('take_config_reset')
 
         if (config != null) {
             userConfig.value = config
@@ -53,10 +107,59 @@ export const useConfigStore = defineStore('config', () => {
         })
     }
 
+    async function patchActiveProfile(values: Partial<NodecgProfile>) {
+        return patch({
+            profiles: userConfig.value.profiles.map(profile =>
+                profile.name === userConfig.value.activeProfile ? { ...profile, ...values } : profile)
+        })
+    }
+
+    // Profile commands save the config on the backend, so it is read again once they are done.
+    async function reload() {
+        const config = await store.get<Configuration>(STORE_CONFIG_KEY)
+        if (config != null) {
+            userConfig.value = config
+        }
+    }
+
+    async function createProfile(name: string, installDir: string | null = null, nodecgVersion: string | null = null) {
+        const profile = await invoke<NodecgProfile>('create_profile', { name, installDir, nodecgVersion })
+        await reload()
+        return profile
+    }
+
+    async function renameProfile(name: string, newName: string) {
+        await invoke('rename_profile', { name, newName })
+        await reload()
+    }
+
+    async function deleteProfile(name: string) {
+        await invoke('delete_profile', { name })
+        await reload()
+    }
+
+    async function activateProfile(name: string) {
+        await invoke('activate_profile', { name })
+        await reload()
+    }
+
+    function dismissConfigReset() {
+        configReset.value = null
+    }
+
     return {
         userConfig: readonly(userConfig),
         allowOpenInTerminal: readonly(allowOpenInTerminal),
+        configReset: readonly(configReset),
+        activeProfile,
+        installDir,
         init,
-        patch
+        patch,
+        patchActiveProfile,
+        createProfile,
+        renameProfile,
+        deleteProfile,
+        activateProfile,
+        dismissConfigReset
     }
 })
@@ -1,21 +1,52 @@
-import { ActionState, LogEvent, ProgressEvent } from '@/types/log'
+import { ActionState, LogEvent, LogFilter, LogHistoryPage, Operation, ProgressEvent } from '@/types/log'
 import { listen, UnlistenFn } from '@tauri-apps/api/event'
 import { defineStore } from 'pinia'
+import { invoke } from '@tauri-apps/api/core'
 
 export interface LogStore {
     lines: Record<string, LogEvent[]>
     progressEntries: Record<string, ProgressEvent>
     actionStates: Record<string, ActionState>
+    operations: Record<number, Operation>
+}
+
+interface OperationClaim {
+    kind: string
+    onStarted: (logKey: string) => void
 }
 
 const unlistenFns: Record<string, UnlistenFn[]> = {}
+// Commands waiting for the backend to register their operation, oldest first
+const pendingClaims: OperationClaim[] = []
+
+export function operationLogKey (operationId: number): string {
+    return `operation-${operationId}`
+}
+
+// Events belonging to an operation are kept apart from other operations sharing the same event key.
+function eventLogKey (key: string, operationId?: number | null): string {
+    return operationId == null ? key : operationLogKey(operationId)
+}
 
 export const useLogStore = defineStore('log', {
     state: () => ({
         lines: {},
         progressEntries: {},
-        actionStates: {}
+        actionStates: {},
+        operations: {}
     } as LogStore),
+    getters: {
+        filteredLines: state => (key: string, filter: LogFilter | null): LogEvent[] => {
+            const lines = state.lines[key] ?? []
+            if (filter == null) {
+                return lines
+            }
+
+            return lines.filter(line =>
+                (filter.levels == null || (line.level != null && filter.levels.includes(line.level)))
+                && (filter.bundle == null || line.bundle === filter.bundle))
+        }
+    },
     actions: {
         insertLine ({ line, key }: { line: LogEvent, key: string }) {
             if (!this.lines[key]) {
@@ -82,17 +113,114 @@ export const useLogStore = defineStore('log', {
 
             const listenPromises: Array<Promise<UnlistenFn>> = [
                 listen<LogEvent>(`log:${key}`, event => {
-                    this.insertLine({ line: event.payload, key })
+                    this.insertLine({ line: event.payload, key: eventLogKey(key, event.payload.operationId) })
                 }),
                 listen<ProgressEvent>(`progress:${key}`, event => {
-                    this.setProgress(key, event.payload)
+                    this.setProgress(eventLogKey(key, event.payload.operationId), event.payload)
                 })
             ]
 
             return Promise.all(listenPromises).then(result => {
                 unlistenFns[key] = result
+                return this.replayHistory(key)
+            })
+        },
+        async replayHistory (key: string) {
+            const history: LogEvent[] = []
+            let since = 0
+            let page: LogHistoryPage
+            do {
+                page = await invoke<LogHistoryPage>('get_log_history', { key, since })
+                history.push(...page.entries)
+                since = page.entries[page.entries.length - 1]?.id ?? since
+            } while (page.hasMore)
+
+            const historyByKey: Record<string, LogEvent[]> = {}
+            history.forEach(line => {
+                const lineKey = eventLogKey(key, line.operationId)
+                if (!historyByKey[lineKey]) {
+                    historyByKey[lineKey] = []
+                }
+                historyByKey[lineKey].push(line)
+            })
+
+            Object.entries(historyByKey).forEach(([lineKey, lines]) => {
+                // Lines received while loading the history may already be part of it
+                const newLines = (this.lines[lineKey] ?? []).filter(line => line.id == null || line.id > since)
+                this.lines[lineKey] = [...lines, ...newLines]
             })
         },
+        async listenForOperations () {
+            const unlisten = await listen<Operation>('operation-update', event => {
+                this.updateOperation(event.payload)
+            })
+            const operations = await invoke<Operation[]>('list_operations')
+            // Updates received while loading the list are newer than its entries
+            operations.forEach(operation => {
+                if (this.operations[operation.id] == null) {
+                    this.operations[operation.id] = operation
+                }
+            })
+            return unlisten
+        },
+        updateOperation (operation: Operation) {
+            const isNew = this.operations[operation.id] == null
+            this.operations[operation.id] = operation
+            const key = operationLogKey(operation.id)
+
+            if (isNew && operation.status === 'Running') {
+                this.actionStates[key] = ActionState.INCOMPLETE
+                const claimIndex = pendingClaims.findIndex(claim => claim.kind === operation.kind)
+                if (claimIndex >= 0) {
+                    pendingClaims.splice(claimIndex, 1)[0].onStarted(key)
+                }
+            }
+
+            switch (operation.status) {
+                case 'Succeeded':
+                    this.setActionState({ key, state: ActionState.COMPLETED_SUCCESS })
+                    break
+                case 'Failed':
+                case 'Cancelled':
+                    this.insertLine({
+                        line: {
+                            message: operation.error ?? 'The operation was cancelled.',
+                            type: 'error'
+                        },
+                        key
+                    })
+                    this.setActionState({ key, state: ActionState.COMPLETED_ERROR })
+                    break
+            }
+        },
+        /**
+         * Runs a command that registers an operation of the given kind. The log is shown under the
+         * kind's key until the backend registers the operation, after which onLogKeyChange receives
+         * the operation's own key.
+         */
+        async runOperation ({ kind, command, onLogKeyChange }: {
+            kind: string,
+            command: () => Promise<unknown>,
+            onLogKeyChange: (logKey: string) => void
+        }) {
+            this.reset(kind)
+            await this.listen(kind)
+            onLogKeyChange(kind)
+
+            const claim: OperationClaim = { kind, onStarted: onLogKeyChange }
+            pendingClaims.push(claim)
+            const invocation = command()
+            this.logPromiseResult({ promise: invocation, key: kind })
+            try {
+                return await invocation
+            } finally {
+                // Commands failing before their operation is registered never claim one
+                const claimIndex = pendingClaims.indexOf(claim)
+                if (claimIndex >= 0) {
+                    pendingClaims.splice(claimIndex, 1)
+                }
+            }
+        },
         unlisten (key: string) {
             if (unlistenFns[key]) {
                 unlistenFns[key].forEach(fn => fn())
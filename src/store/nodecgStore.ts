@@ -1,8 +1,9 @@
-import { Bundle, getBundles, getNodecgStatus } from '@/service/nodecgService'
+import { Bundle, getBundles, getNodecgStatus, runLogKey } from '@/service/nodecgService'
 import { useConfigStore } from '@/store/configStore'
 import { defineStore } from 'pinia'
 import { listen } from '@tauri-apps/api/event'
 import { useLogStore } from '@/store/logStore'
+import { invoke } from '@tauri-apps/api/core'
 
 export enum InstallStatus {
     UNKNOWN,
@@ -19,10 +20,18 @@ export enum RunStatus {
     STOPPED = 'STOPPED'
 }
 
+export type NodecgProcessStatus = 'NotRunning' | 'Starting' | 'Ready' | 'Unhealthy' | 'Stopping' | 'Stopped' | 'Crashed' | 'Restarting'
+
+export interface NodecgStatusChange {
+    profile: string
+    status: NodecgProcessStatus
+}
+
 export interface NodecgStore {
     status: {
         installStatus: InstallStatus
         runStatus: RunStatus
+        processStatus: NodecgProcessStatus
         message: string
         bundlesLoading: boolean
     },
@@ -34,6 +43,7 @@ export const useNodecgStore = defineStore('nodecg', {
         status: {
             installStatus: InstallStatus.UNKNOWN,
             runStatus: RunStatus.NOT_STARTED,
+            processStatus: 'NotRunning',
             message: '',
             bundlesLoading: false
         },
@@ -44,7 +54,7 @@ export const useNodecgStore = defineStore('nodecg', {
             const configStore = useConfigStore()
 
             try {
-                const { status, message } = await getNodecgStatus(configStore.userConfig.nodecgInstallDir)
+                const { status, message } = await getNodecgStatus(configStore.installDir)
                 this.status.message = message
                 this.status.installStatus = status
                 if (status === InstallStatus.INSTALLED) {
@@ -62,20 +72,50 @@ export const useNodecgStore = defineStore('nodecg', {
             const configStore = useConfigStore()
             this.status.bundlesLoading = true
             try {
-                this.bundles = await getBundles(configStore.userConfig.nodecgInstallDir)
+                this.bundles = await getBundles(configStore.installDir)
             } finally {
                 this.status.bundlesLoading = false
             }
         },
         async listenForRunStatus () {
+            const configStore = useConfigStore()
             const logStore = useLogStore()
-            return listen<'NotRunning' | 'Running'>('nodecg-status-change', event => {
-                const isRunning = event.payload === 'Running'
-                if (isRunning) {
-                    logStore.reset('run-nodecg')
+            this.setProcessStatus(await invoke<NodecgProcessStatus>('get_nodecg_status'))
+            // Only the instance of the active profile is shown
+            return listen<NodecgStatusChange>('nodecg-status-change', event => {
+                if (event.payload.profile !== configStore.userConfig.activeProfile) {
+                    return
+                }
+                if (event.payload.status === 'Starting' && this.status.processStatus !== 'Restarting') {
+                    logStore.reset(runLogKey(event.payload.profile))
                 }
-                this.status.runStatus = isRunning ? RunStatus.RUNNING : RunStatus.STOPPED
+                this.setProcessStatus(event.payload.status)
             })
+        },
+        async activateProfile (name: string) {
+            const configStore = useConfigStore()
+            const logStore = useLogStore()
+            await configStore.activateProfile(name)
+            await logStore.listen(runLogKey(name))
+            this.setProcessStatus(await invoke<NodecgProcessStatus>('get_nodecg_status'))
+            await this.checkNodecgStatus()
+        },
+        setProcessStatus (processStatus: NodecgProcessStatus) {
+            this.status.processStatus = processStatus
+            switch (processStatus) {
+                case 'NotRunning':
+                    this.status.runStatus = RunStatus.NOT_STARTED
+                    break
+                case 'Starting':
+                case 'Ready':
+                case 'Unhealthy':
+                case 'Stopping':
+                case 'Restarting':
+                    this.status.runStatus = RunStatus.RUNNING
+                    break
+                default:
+                    this.status.runStatus = RunStatus.STOPPED
+            }
         }
     }
 })
@@ -1,12 +1,42 @@
+export type LogLevel = 'trace' | 'debug' | 'info' | 'warn' | 'error'
+
 export interface LogEvent {
+    id?: number
+    operationId?: number | null
     message: string
     type?: 'success' | 'info' | 'error'
+    stream?: 'stdout' | 'stderr' | null
+    timestamp?: number
+    level?: LogLevel
+    bundle?: string | null
+    text?: string
+}
+
+export interface LogFilter {
+    levels?: LogLevel[]
+    bundle?: string
+}
+
+export interface LogHistoryPage {
+    entries: LogEvent[]
+    hasMore: boolean
 }
 
 export interface ProgressEvent {
     message: string
     step?: number
     maxStep?: number
+    operationId?: number | null
+}
+
+export interface Operation {
+    id: number
+    kind: string
+    description: string
+    status: 'Running' | 'Succeeded' | 'Failed' | 'Cancelled'
+    error: string | null
+    startedAt: number
+    finishedAt: number | null
 }
 
 export enum ActionState {
@@ -1,3 +1,11 @@
 export interface NodecgConfiguration {
     port: number
 }
+
+export interface TrashedBundle {
+    id: string
+    bundleName: string
+    deletedAt: number
+    expiresAt: number | null
+    hasConfig: boolean
+}